@@ -0,0 +1,77 @@
+use colored::Colorize;
+use std::env;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether or not build script mode was explicitly turned on.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Environment variables that Cargo only sets when running build scripts.
+const BUILD_SCRIPT_VARS: &[&str] = &["OUT_DIR", "TARGET"];
+
+/// Turn on build script mode for the rest of the process. Does not need to be called when running
+/// from a build script, but can't hurt either.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether or not ZAMM is running from a build script, either because build script mode was turned
+/// on or because Cargo set the variables that it only sets for build scripts. Build script mode
+/// tells Cargo about every file and environment variable that ZAMM reads, so that the build script
+/// reruns whenever any of them change.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed) || BUILD_SCRIPT_VARS.iter().all(|v| env::var_os(v).is_some())
+}
+
+/// Rerun the build script whenever the given file changes.
+pub fn rerun_if_changed<P: AsRef<Path>>(path: P) {
+    if is_enabled() {
+        println!("cargo:rerun-if-changed={}", path.as_ref().display());
+    }
+}
+
+/// Rerun the build script whenever the given environment variable changes.
+pub fn rerun_if_env_changed(name: &str) {
+    if is_enabled() {
+        println!("cargo:rerun-if-env-changed={}", name);
+    }
+}
+
+/// Cargo instructions for displaying a warning. Cargo only takes single-line warnings, so each
+/// line gets its own instruction.
+fn cargo_warnings(warning: &str) -> Vec<String> {
+    warning
+        .lines()
+        .map(|line| format!("cargo:warning={}", line))
+        .collect()
+}
+
+/// Print a warning. In build script mode, the warning is handed to Cargo to display, since Cargo
+/// hides all other build script output unless the build fails or is very verbose. Otherwise, it
+/// goes to stderr so that it never gets mixed into output meant for other tools.
+pub fn print_warning<S: AsRef<str>>(warning: S) {
+    let warning = warning.as_ref();
+    if is_enabled() {
+        for line in cargo_warnings(warning) {
+            println!("{}", line);
+        }
+    } else {
+        eprintln!("{}", warning.yellow().bold());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiline_warning() {
+        assert_eq!(
+            cargo_warnings("Import failed\nFalling back to the cached copy instead."),
+            vec![
+                "cargo:warning=Import failed",
+                "cargo:warning=Falling back to the cached copy instead."
+            ]
+        );
+    }
+}
@@ -0,0 +1,6 @@
+use sha2::{Digest, Sha256};
+
+/// Lowercase hex encoding of the SHA-256 checksum of the given bytes.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
@@ -1,17 +1,35 @@
+use crate::intermediate_build::{clean_shared_target, Manifest};
+use crate::project::Project;
 use std::fs::{remove_dir_all, remove_file, File};
 use std::io::{BufRead, BufReader, Result};
-use std::path::Path;
 
 /// The path to the file that tracks autogenerated files.
 pub const AUTOGEN_TRACKER: &str = ".autogen.txt";
 
-/// Clean up all autogenerated files.
-fn clean_autogen() -> Result<()> {
-    match File::open(AUTOGEN_TRACKER) {
+/// Clean up every file that the last build's manifest lists.
+fn clean_manifest_files(project: &Project) -> Result<()> {
+    if let Some(manifest) = Manifest::load(project)? {
+        for file in manifest.files {
+            match remove_file(project.path(&file.path)) {
+                Ok(_) => (), // be silent on success
+                Err(_) => println!(
+                    "Skipping deletion of {}. It might not exist anymore.",
+                    file.path.display()
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Clean up all autogenerated files. The tracked filenames are relative to the project root.
+fn clean_autogen(project: &Project) -> Result<()> {
+    let tracker = project.path(AUTOGEN_TRACKER);
+    match File::open(&tracker) {
         Ok(existing_tracker) => {
             for line in BufReader::new(existing_tracker).lines() {
                 let filename = line.unwrap();
-                match remove_file(&filename) {
+                match remove_file(project.path(&filename)) {
                     Ok(_) => (), // be silent on success
                     Err(_) => println!(
                         "Skipping deletion of {}. It might not exist anymore.",
@@ -19,7 +37,7 @@ fn clean_autogen() -> Result<()> {
                     ),
                 }
             }
-            remove_file(AUTOGEN_TRACKER)?;
+            remove_file(&tracker)?;
         }
         Err(_) => println!("No autogenerated files to clean up."),
     }
@@ -27,8 +45,8 @@ fn clean_autogen() -> Result<()> {
 }
 
 /// Clean up ZAMM build directory.
-fn clean_zamm_build() -> Result<()> {
-    let dot_zamm = Path::new(".zamm");
+fn clean_zamm_build(project: &Project) -> Result<()> {
+    let dot_zamm = project.build_dir();
     if dot_zamm.exists() {
         remove_dir_all(dot_zamm)
     } else {
@@ -37,8 +55,12 @@ fn clean_zamm_build() -> Result<()> {
     }
 }
 
-/// Clean ZAMM build directory, as well as generated files.
-pub fn clean() -> Result<()> {
-    clean_autogen()?;
-    clean_zamm_build()
+/// Clean ZAMM build directory, as well as generated files, whether they were listed in the manifest
+/// of the last build or tracked by Yang. This project's artifacts in the shared
+/// intermediate target directory get removed too.
+pub fn clean(project: &Project) -> Result<()> {
+    clean_manifest_files(project)?;
+    clean_autogen(project)?;
+    clean_shared_target(project, &project.config()?.build)?;
+    clean_zamm_build(project)
 }
@@ -0,0 +1,271 @@
+use crate::parse::{redact_url, resolve_input_imports, ImportNode, ImportOrigin, ParseConfig};
+use path_abs::{PathAbs, PathInfo};
+use reqwest::Url;
+use semver::Version;
+use serde::Serialize;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Ways to display the import graph.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GraphFormat {
+    /// Human-readable tree.
+    Text,
+    /// JSON, for tooling.
+    Json,
+    /// Graphviz DOT, for diagrams.
+    Dot,
+}
+
+impl FromStr for GraphFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(GraphFormat::Text),
+            "json" => Ok(GraphFormat::Json),
+            "dot" => Ok(GraphFormat::Dot),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Unknown import graph format \"{}\". Use one of text, json, or dot.",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+/// Everything worth knowing about an import, for display purposes.
+#[derive(Debug, Serialize)]
+struct ImportSummary {
+    /// The import as it was written in the importing file.
+    import: String,
+    /// Where the import was actually retrieved from.
+    location: String,
+    /// The version of the imported book, if it can be told from the location.
+    version: Option<String>,
+    /// Where the contents of the import came from.
+    origin: ImportOrigin,
+    /// The imports of this import.
+    imports: Vec<ImportSummary>,
+}
+
+/// The entire import graph, for display purposes.
+#[derive(Debug, Serialize)]
+struct ImportGraph {
+    /// The main input file.
+    input: String,
+    /// The imports of the main input file.
+    imports: Vec<ImportSummary>,
+}
+
+/// Tell the version of a book from its location, by looking for a path segment that is a valid
+/// semantic version, as in `https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md`.
+fn version_of(location: &str) -> Option<String> {
+    let segments: Vec<String> = match Url::parse(location) {
+        Ok(url) if url.has_host() => url
+            .path_segments()
+            .into_iter()
+            .flatten()
+            .map(str::to_owned)
+            .collect(),
+        _ => Path::new(location)
+            .iter()
+            .map(|s| s.to_string_lossy().into_owned())
+            .collect(),
+    };
+    segments
+        .iter()
+        .rev()
+        .find(|s| Version::parse(s).is_ok())
+        .cloned()
+}
+
+fn summarize(node: &ImportNode) -> ImportSummary {
+    ImportSummary {
+        import: redact_url(node.written.trim()),
+        location: redact_url(&node.spec.location),
+        version: version_of(&node.spec.location),
+        origin: node.origin,
+        imports: node.children.iter().map(summarize).collect(),
+    }
+}
+
+fn describe(summary: &ImportSummary) -> String {
+    let mut details = vec![];
+    if let Some(version) = &summary.version {
+        details.push(format!("v{}", version));
+    }
+    details.push(
+        match summary.origin {
+            ImportOrigin::Local => "local",
+            ImportOrigin::Network => "network",
+            ImportOrigin::Cache => "network, served from cache",
+        }
+        .to_owned(),
+    );
+    let location = if summary.import == summary.location {
+        summary.location.clone()
+    } else {
+        format!("{} -> {}", summary.import, summary.location)
+    };
+    format!("{} ({})", location, details.join(", "))
+}
+
+fn render_text_children(imports: &[ImportSummary], prefix: &str, output: &mut String) {
+    for (i, summary) in imports.iter().enumerate() {
+        let last = i == imports.len() - 1;
+        *output += &format!(
+            "{}{}{}\n",
+            prefix,
+            if last { "└── " } else { "├── " },
+            describe(summary)
+        );
+        let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        render_text_children(&summary.imports, &child_prefix, output);
+    }
+}
+
+fn render_text(graph: &ImportGraph) -> String {
+    let mut output = format!("{}\n", graph.input);
+    render_text_children(&graph.imports, "", &mut output);
+    output
+}
+
+fn dot_quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+fn render_dot_edges(parent: &str, imports: &[ImportSummary], output: &mut String) {
+    for summary in imports {
+        let label = describe(summary).replace(" (", "\n(");
+        *output += &format!(
+            "    {} [label={}];\n    {} -> {};\n",
+            dot_quote(&summary.location),
+            dot_quote(&label),
+            dot_quote(parent),
+            dot_quote(&summary.location)
+        );
+        render_dot_edges(&summary.location, &summary.imports, output);
+    }
+}
+
+fn render_dot(graph: &ImportGraph) -> String {
+    let mut output = format!(
+        "digraph imports {{\n    {} [shape=box];\n",
+        dot_quote(&graph.input)
+    );
+    render_dot_edges(&graph.input, &graph.imports, &mut output);
+    output += "}\n";
+    output
+}
+
+/// Print out the tree of everything that the input file imports.
+pub fn print_imports(
+    found_input: &PathAbs,
+    parse_cfg: &ParseConfig,
+    format: GraphFormat,
+) -> Result<()> {
+    let parse_cfg = ParseConfig {
+        // keep stdout clean for tools consuming the output
+        quiet: parse_cfg.quiet || format != GraphFormat::Text,
+        ..parse_cfg.clone()
+    };
+    let tree = resolve_input_imports(found_input, &parse_cfg)?;
+    let graph = ImportGraph {
+        input: found_input.as_os_str().to_str().unwrap().to_owned(),
+        imports: tree.iter().map(summarize).collect(),
+    };
+    match format {
+        GraphFormat::Text => print!("{}", render_text(&graph)),
+        GraphFormat::Json => println!("{}", serde_json::to_string_pretty(&graph)?),
+        GraphFormat::Dot => print!("{}", render_dot(&graph)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    fn example_graph() -> ImportGraph {
+        ImportGraph {
+            input: "yin.md".to_owned(),
+            imports: vec![
+                ImportSummary {
+                    import: "https://api.zamm.dev/v1/books/zamm/yang/0.1.5/yin.md".to_owned(),
+                    location: "https://mirror.corp/v1/books/zamm/yang/0.1.5/yin.md".to_owned(),
+                    version: Some("0.1.5".to_owned()),
+                    origin: ImportOrigin::Network,
+                    imports: vec![ImportSummary {
+                        import: "https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md".to_owned(),
+                        location: "https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md".to_owned(),
+                        version: Some("0.1.0".to_owned()),
+                        origin: ImportOrigin::Cache,
+                        imports: vec![],
+                    }],
+                },
+                ImportSummary {
+                    import: "helpers.md".to_owned(),
+                    location: "helpers.md".to_owned(),
+                    version: None,
+                    origin: ImportOrigin::Local,
+                    imports: vec![],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_version_of() {
+        assert_eq!(
+            version_of("https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md"),
+            Some("0.1.0".to_owned())
+        );
+        assert_eq!(
+            version_of("zamm-vendor/api.zamm.dev/v1/books/zamm/yin/0.2.0-beta/yin.md"),
+            Some("0.2.0-beta".to_owned())
+        );
+        assert_eq!(version_of("../yin.md"), None);
+    }
+
+    #[test]
+    fn test_render_text() {
+        assert_eq!(
+            render_text(&example_graph()),
+            indoc! {"
+                yin.md
+                ├── https://api.zamm.dev/v1/books/zamm/yang/0.1.5/yin.md -> https://mirror.corp/v1/books/zamm/yang/0.1.5/yin.md (v0.1.5, network)
+                │   └── https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md (v0.1.0, network, served from cache)
+                └── helpers.md (local)
+            "}
+        );
+    }
+
+    #[test]
+    fn test_render_dot() {
+        let dot = render_dot(&example_graph());
+        assert!(dot.starts_with("digraph imports {\n    \"yin.md\" [shape=box];\n"));
+        assert!(dot.contains("    \"yin.md\" -> \"helpers.md\";\n"));
+        assert!(dot.contains(
+            "    \"https://mirror.corp/v1/books/zamm/yang/0.1.5/yin.md\" -> \
+            \"https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md\";\n"
+        ));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let json = serde_json::to_value(example_graph()).unwrap();
+        assert_eq!(json["imports"][0]["origin"], "network");
+        assert_eq!(json["imports"][0]["imports"][0]["origin"], "cache");
+        assert_eq!(json["imports"][1]["version"], serde_json::Value::Null);
+    }
+}
@@ -1,5 +1,11 @@
 mod clean_autogen;
+mod import_graph;
 mod run_command;
+mod vendor;
 
-pub use clean_autogen::clean;
-pub use run_command::{run_command, run_streamed_command};
+pub use clean_autogen::{clean, AUTOGEN_TRACKER};
+pub use import_graph::{print_imports, GraphFormat};
+pub use run_command::{
+    run_command, run_command_streaming_stderr, run_streamed_command, run_streamed_command_with_env,
+};
+pub use vendor::{vendor, VENDOR_DIR};
@@ -1,9 +1,17 @@
 use itertools::Itertools;
 use std::ffi::OsStr;
 use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
 use std::process::{Command, Stdio};
 
-fn run_command_base<I, S>(stream_output: bool, command_name: &str, args: I) -> Result<String>
+fn run_command_base<I, S>(
+    stream_stdout: bool,
+    stream_stderr: bool,
+    command_name: &str,
+    args: I,
+    envs: &[(&str, &str)],
+    dir: Option<&Path>,
+) -> Result<String>
 where
     I: IntoIterator<Item = S> + Clone,
     S: AsRef<OsStr> + std::fmt::Display,
@@ -15,8 +23,15 @@ where
     );
 
     let mut command = Command::new(command_name);
-    if stream_output {
-        command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    if stream_stdout {
+        command.stdout(Stdio::inherit());
+    }
+    if stream_stderr {
+        command.stderr(Stdio::inherit());
+    }
+    command.envs(envs.iter().cloned());
+    if let Some(dir) = dir {
+        command.current_dir(dir);
     }
     let result = command.args(args).output()?;
 
@@ -38,7 +53,7 @@ where
     I: IntoIterator<Item = S> + Clone,
     S: AsRef<OsStr> + std::fmt::Display,
 {
-    run_command_base(false, command, args)
+    run_command_base(false, false, command, args, &[], None)
 }
 
 /// Run a command that streams to stdout. Returns stderr output on failure.
@@ -47,6 +62,37 @@ where
     I: IntoIterator<Item = S> + Clone,
     S: AsRef<OsStr> + std::fmt::Display,
 {
-    run_command_base(true, command, args)?;
+    run_command_base(true, true, command, args, &[], None)?;
     Ok(())
 }
+
+/// Run a command in the given directory that streams to stdout, with extra environment variables
+/// set for it. Returns stderr output on failure.
+pub fn run_streamed_command_with_env<I, S>(
+    command: &str,
+    args: I,
+    envs: &[(&str, &str)],
+    dir: &Path,
+) -> Result<()>
+where
+    I: IntoIterator<Item = S> + Clone,
+    S: AsRef<OsStr> + std::fmt::Display,
+{
+    run_command_base(true, true, command, args, envs, Some(dir))?;
+    Ok(())
+}
+
+/// Run a command in the given directory that streams its stderr output, but not its stdout output.
+/// Returns stdout output on success.
+pub fn run_command_streaming_stderr<I, S>(
+    command: &str,
+    args: I,
+    envs: &[(&str, &str)],
+    dir: &Path,
+) -> Result<String>
+where
+    I: IntoIterator<Item = S> + Clone,
+    S: AsRef<OsStr> + std::fmt::Display,
+{
+    run_command_base(false, true, command, args, envs, Some(dir))
+}
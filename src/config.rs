@@ -0,0 +1,276 @@
+use crate::build_script;
+use crate::intermediate_build::{BuildConfig, CodegenConfig};
+use crate::parse::{Mirrors, ParseConfig};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// Filename for the project configuration file.
+pub const ZAMM_CONFIG_NAME: &str = "zamm.toml";
+
+/// Filename for the project Cargo file, which can hold the configuration under
+/// `[package.metadata.zamm]` instead.
+const CARGO_FILE: &str = "Cargo.toml";
+
+/// Defaults for the codegen settings. Anything left unset falls back to `CodegenConfig::default`,
+/// and commandline flags take precedence over everything set here.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CodegenSettings {
+    /// Default for `CodegenConfig::comment_autogen`.
+    pub comment_autogen: Option<bool>,
+    /// Default for `CodegenConfig::add_rustfmt_attributes`.
+    pub add_rustfmt_attributes: Option<bool>,
+    /// Default for `CodegenConfig::track_autogen`.
+    pub track_autogen: Option<bool>,
+    /// Default for `CodegenConfig::yin`.
+    pub yin: Option<bool>,
+}
+
+/// Git branches used by `zamm release`.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ReleaseSettings {
+    /// The main development branch.
+    pub main_branch: String,
+    /// Long-running release branch.
+    pub branch: String,
+    /// Short-lived temp branch for commit munging.
+    pub temp_branch: String,
+}
+
+impl Default for ReleaseSettings {
+    fn default() -> Self {
+        Self {
+            main_branch: "main".to_owned(),
+            branch: "releases".to_owned(),
+            temp_branch: "zamm-temp-release".to_owned(),
+        }
+    }
+}
+
+/// Where `zamm release` uploads the input file to.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct StorageSettings {
+    /// GCS bucket containing all build files. The bucket is expected to be served at a domain of
+    /// the same name.
+    pub bucket: String,
+    /// Path within the bucket that books get uploaded under.
+    pub prefix: String,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            bucket: "api.zamm.dev".to_owned(),
+            prefix: "v1/books/zamm".to_owned(),
+        }
+    }
+}
+
+/// Project-wide settings, read from `zamm.toml` or from `[package.metadata.zamm]` in `Cargo.toml`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ZammConfig {
+    /// The input file to use when none is specified.
+    pub input: Option<String>,
+    /// Defaults for the codegen settings.
+    pub codegen: CodegenSettings,
+    /// Override files to apply after the user and project override files.
+    pub overrides: Vec<PathBuf>,
+    /// Import location prefixes, mapped to what they should be rewritten to.
+    pub mirrors: BTreeMap<String, String>,
+    /// Git branches used by `zamm release`.
+    pub release: ReleaseSettings,
+    /// Where `zamm release` uploads the input file to.
+    pub storage: StorageSettings,
+    /// Options for building the intermediate codegen binary.
+    pub build: BuildConfig,
+}
+
+fn invalid_config(file: &Path, error: impl std::fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid ZAMM config in {}: {}", file.display(), error),
+    )
+}
+
+impl ZammConfig {
+    /// Parse the contents of a `zamm.toml` file.
+    pub fn parse(contents: &str) -> std::result::Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// Load the configuration for the project in the given directory. `zamm.toml` is used if it
+    /// exists, followed by `[package.metadata.zamm]` in `Cargo.toml`. If neither exists, the
+    /// defaults are used. Build scripts are rerun if either file gets created, not just changed.
+    pub fn load_from(dir: &Path) -> Result<Self> {
+        let config_file = dir.join(ZAMM_CONFIG_NAME);
+        build_script::rerun_if_changed(&config_file);
+        if config_file.exists() {
+            return Self::parse(&read_to_string(&config_file)?)
+                .map_err(|e| invalid_config(&config_file, e));
+        }
+
+        let cargo_file = dir.join(CARGO_FILE);
+        build_script::rerun_if_changed(&cargo_file);
+        if cargo_file.exists() {
+            let cargo_toml = read_to_string(&cargo_file)?
+                .parse::<Value>()
+                .map_err(|e| invalid_config(&cargo_file, e))?;
+            let metadata = cargo_toml
+                .get("package")
+                .and_then(|p| p.get("metadata"))
+                .and_then(|m| m.get("zamm"));
+            if let Some(metadata) = metadata {
+                return metadata
+                    .clone()
+                    .try_into()
+                    .map_err(|e| invalid_config(&cargo_file, e));
+            }
+        }
+        Ok(Self::default())
+    }
+
+    /// Load the configuration for the project in the current working directory.
+    pub fn load() -> Result<Self> {
+        Self::load_from(Path::new("."))
+    }
+
+    /// The codegen settings to use when the commandline doesn't say otherwise.
+    pub fn codegen_config(&self) -> CodegenConfig {
+        let defaults = CodegenConfig::default();
+        let settings = &self.codegen;
+        CodegenConfig {
+            comment_autogen: settings.comment_autogen.unwrap_or(defaults.comment_autogen),
+            add_rustfmt_attributes: settings
+                .add_rustfmt_attributes
+                .unwrap_or(defaults.add_rustfmt_attributes),
+            track_autogen: settings.track_autogen.unwrap_or(defaults.track_autogen),
+            yin: settings.yin.unwrap_or(defaults.yin),
+            ..defaults
+        }
+    }
+
+    /// Add the mirrors and override files from this configuration to the parse settings. Settings
+    /// from the environment take precedence over the ones from this configuration. Override files
+    /// are taken to be relative to the project root in the parse settings.
+    pub fn apply_to(&self, parse_cfg: &mut ParseConfig) {
+        let mut mirrors = Mirrors::default();
+        for (prefix, replacement) in &self.mirrors {
+            mirrors.add(prefix, replacement);
+        }
+        // later rewrites win over earlier ones with the same prefix
+        mirrors.rewrites.append(&mut parse_cfg.mirrors.rewrites);
+        parse_cfg.mirrors = mirrors;
+
+        let mut overrides: Vec<PathBuf> = self
+            .overrides
+            .iter()
+            .map(|o| parse_cfg.project_root.join(o))
+            .collect();
+        overrides.append(&mut parse_cfg.overrides);
+        parse_cfg.overrides = overrides;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use std::fs;
+
+    #[test]
+    fn test_parse_defaults() {
+        assert_eq!(ZammConfig::parse("").unwrap(), ZammConfig::default());
+    }
+
+    #[test]
+    fn test_parse_full() {
+        let config = ZammConfig::parse(indoc! {r#"
+            input = "book.md"
+            overrides = ["local.md"]
+
+            [codegen]
+            comment-autogen = false
+            yin = true
+
+            [mirrors]
+            "https://api.zamm.dev/" = "https://mirror.corp/zamm/"
+
+            [release]
+            branch = "published"
+
+            [build]
+            cache-size = 2
+        "#})
+        .unwrap();
+        assert_eq!(config.input, Some("book.md".to_owned()));
+        assert_eq!(config.overrides, vec![PathBuf::from("local.md")]);
+        assert_eq!(config.release.branch, "published");
+        assert_eq!(config.release.temp_branch, "zamm-temp-release");
+        assert_eq!(config.storage, StorageSettings::default());
+        assert_eq!(config.build.cache_size, 2);
+
+        let codegen_cfg = config.codegen_config();
+        assert!(!codegen_cfg.comment_autogen);
+        assert!(codegen_cfg.yin);
+        assert!(codegen_cfg.add_rustfmt_attributes);
+    }
+
+    #[test]
+    fn test_apply_to_parse_config() {
+        let config = ZammConfig::parse(indoc! {r#"
+            overrides = ["project.md"]
+
+            [mirrors]
+            "https://api.zamm.dev/" = "https://mirror.corp/zamm/"
+        "#})
+        .unwrap();
+        let mut parse_cfg = ParseConfig {
+            mirrors: Mirrors::parse("https://api.zamm.dev/=file:///srv/zamm/").unwrap(),
+            overrides: vec![PathBuf::from("cli.md")],
+            ..ParseConfig::default()
+        };
+        config.apply_to(&mut parse_cfg);
+        assert_eq!(
+            parse_cfg.mirrors.resolve("https://api.zamm.dev/yin.md"),
+            "/srv/zamm/yin.md"
+        );
+        assert_eq!(
+            parse_cfg.overrides,
+            vec![PathBuf::from("project.md"), PathBuf::from("cli.md")]
+        );
+    }
+
+    #[test]
+    fn test_load_from_cargo_metadata() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(
+            dir.join(CARGO_FILE),
+            indoc! {r#"
+                [package]
+                name = "example"
+
+                [package.metadata.zamm]
+                input = "book.md"
+            "#},
+        )
+        .unwrap();
+        assert_eq!(
+            ZammConfig::load_from(dir).unwrap().input,
+            Some("book.md".to_owned())
+        );
+
+        fs::write(dir.join(ZAMM_CONFIG_NAME), "input = \"other.md\"").unwrap();
+        assert_eq!(
+            ZammConfig::load_from(dir).unwrap().input,
+            Some("other.md".to_owned())
+        );
+    }
+}
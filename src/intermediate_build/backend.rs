@@ -0,0 +1,234 @@
+use super::build_logic::{dry_run_final_code, generate_final_code};
+use super::{BuildConfig, CodegenConfig, INTERMEDIATE_LOCK_FILE};
+use crate::commands::AUTOGEN_TRACKER;
+use crate::parse::ParseOutput;
+use crate::project::Project;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// A code generator that turns a parsed input file into outputs. Implement this to reuse ZAMM's
+/// parsing, imports, and release tooling with a different code generator.
+pub trait Backend {
+    /// Generate outputs for the project from its input file, which has already had its imports
+    /// retrieved and its overrides applied.
+    fn generate(
+        &self,
+        project: &Project,
+        input: &ParseOutput,
+        codegen_cfg: &CodegenConfig,
+    ) -> Result<()>;
+}
+
+/// Build settings for the given input file, taking its front matter into account. The
+/// intermediate lock file goes next to the input file unless configured otherwise.
+fn document_build_config(input: &ParseOutput, build_cfg: &BuildConfig) -> BuildConfig {
+    BuildConfig {
+        template: input
+            .front_matter
+            .template
+            .clone()
+            .or_else(|| build_cfg.template.clone()),
+        lock_file: build_cfg
+            .lock_file
+            .clone()
+            .or_else(|| Some(input.path.with_file_name(INTERMEDIATE_LOCK_FILE))),
+        ..build_cfg.clone()
+    }
+}
+
+/// The default backend, which builds a Cargo binary out of the literate code and runs it. A main
+/// template set in the input file's front matter takes precedence over the one in the build
+/// settings.
+#[derive(Debug, Clone, Default)]
+pub struct CargoBackend {
+    /// How to build the codegen binary.
+    pub build_cfg: BuildConfig,
+}
+
+impl Backend for CargoBackend {
+    fn generate(
+        &self,
+        project: &Project,
+        input: &ParseOutput,
+        codegen_cfg: &CodegenConfig,
+    ) -> Result<()> {
+        generate_final_code(
+            project,
+            &input.extractions,
+            codegen_cfg,
+            &document_build_config(input, &self.build_cfg),
+        )
+    }
+}
+
+/// A backend that writes the literate Rust code straight to a file, without running it.
+#[derive(Debug, Clone)]
+pub struct TangleBackend {
+    /// File to write the Rust code to, relative to the project root.
+    pub output: PathBuf,
+}
+
+impl Backend for TangleBackend {
+    fn generate(&self, project: &Project, input: &ParseOutput, _: &CodegenConfig) -> Result<()> {
+        let output = project.path(&self.output);
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let code = &input.extractions;
+        fs::write(&output, format!("{}{}", code.imported_rust, code.rust))?;
+        println!("Tangled code written to {}", output.display());
+        Ok(())
+    }
+}
+
+/// A backend that runs the codegen binary on a scratch copy of the project, and prints a unified
+/// diff of every file that a real build would change. The project itself is left untouched.
+#[derive(Debug, Clone, Default)]
+pub struct DryRunBackend {
+    /// How to build the codegen binary.
+    pub build_cfg: BuildConfig,
+}
+
+impl Backend for DryRunBackend {
+    fn generate(
+        &self,
+        project: &Project,
+        input: &ParseOutput,
+        codegen_cfg: &CodegenConfig,
+    ) -> Result<()> {
+        let changes = dry_run_final_code(
+            project,
+            &input.extractions,
+            codegen_cfg,
+            &document_build_config(input, &self.build_cfg),
+        )?;
+        println!("==================== DRY RUN ====================");
+        if changes.is_empty() {
+            println!("No files would change.");
+        }
+        for change in &changes {
+            print!("{}", change.diff());
+        }
+        Ok(())
+    }
+}
+
+/// A backend that makes sure the generated files in the project are up to date with the input
+/// file, by regenerating them on a scratch copy of the project. Fails with a list of every stale
+/// file if they aren't, so that CI can catch input files that were edited without rebuilding.
+#[derive(Debug, Clone, Default)]
+pub struct CheckBackend {
+    /// How to build the codegen binary.
+    pub build_cfg: BuildConfig,
+}
+
+impl Backend for CheckBackend {
+    fn generate(
+        &self,
+        project: &Project,
+        input: &ParseOutput,
+        codegen_cfg: &CodegenConfig,
+    ) -> Result<()> {
+        let changes: Vec<_> = dry_run_final_code(
+            project,
+            &input.extractions,
+            codegen_cfg,
+            &document_build_config(input, &self.build_cfg),
+        )?
+        .into_iter()
+        // the tracker is rewritten by every build, whether or not anything else changed
+        .filter(|c| c.path != Path::new(AUTOGEN_TRACKER))
+        .collect();
+        if changes.is_empty() {
+            println!("Generated files are up to date with {}.", input.filename);
+            return Ok(());
+        }
+        for change in &changes {
+            println!("Diff in {}:", change.path.display());
+            print!("{}", change.diff());
+        }
+        let summaries: Vec<String> = changes
+            .iter()
+            .map(|c| format!("  {}", c.summary()))
+            .collect();
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} generated files are out of date with {}:\n{}\nRun `zamm build` to regenerate \
+                them.",
+                changes.len(),
+                input.filename,
+                summaries.join("\n")
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{CodeExtraction, FrontMatter};
+
+    fn parse_output(template: Option<&str>) -> ParseOutput {
+        ParseOutput {
+            filename: "yin.md".to_owned(),
+            path: PathBuf::from("/book/yin.md"),
+            markdown: String::new(),
+            extractions: CodeExtraction {
+                rust: "let x = 1;".to_owned(),
+                ..CodeExtraction::default()
+            },
+            front_matter: FrontMatter {
+                template: template.map(str::to_owned),
+            },
+        }
+    }
+
+    #[test]
+    fn test_front_matter_template_precedence() {
+        let build_cfg = BuildConfig {
+            template: Some("yin".to_owned()),
+            ..BuildConfig::default()
+        };
+        assert_eq!(
+            document_build_config(&parse_output(Some("plain")), &build_cfg).template,
+            Some("plain".to_owned())
+        );
+        assert_eq!(
+            document_build_config(&parse_output(None), &build_cfg).template,
+            Some("yin".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_lock_file_next_to_input() {
+        let build_cfg = document_build_config(&parse_output(None), &BuildConfig::default());
+        assert_eq!(
+            build_cfg.lock_file,
+            Some(PathBuf::from("/book/intermediate.lock"))
+        );
+        let configured = BuildConfig {
+            lock_file: Some(PathBuf::from("locks/yin.lock")),
+            ..BuildConfig::default()
+        };
+        assert_eq!(
+            document_build_config(&parse_output(None), &configured).lock_file,
+            Some(PathBuf::from("locks/yin.lock"))
+        );
+    }
+
+    #[test]
+    fn test_tangle_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("tangled.rs");
+        let backend = TangleBackend {
+            output: output.clone(),
+        };
+        let project = Project::current().unwrap();
+        backend
+            .generate(&project, &parse_output(None), &CodegenConfig::default())
+            .unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "let x = 1;");
+    }
+}
@@ -0,0 +1,220 @@
+use super::dry_run::project_files;
+use crate::checksum::sha256_hex;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// File in the cache directory listing cached binaries, most recently used first.
+const CACHE_INDEX: &str = "index.json";
+
+/// Identify a codegen binary by everything that goes into building it.
+pub fn cache_key(main_rs: &str, cargo_toml: &str, toolchain: &str) -> String {
+    // separate the parts so that moving text from one into another changes the key
+    sha256_hex([main_rs, cargo_toml, toolchain].join("\0").as_bytes())
+}
+
+/// Directories of the path dependencies in the given manifest. Relative paths are resolved against
+/// `manifest_dir`, and dependencies that don't exist are left for Cargo to complain about.
+fn path_dependencies(cargo_toml: &str, manifest_dir: &Path) -> Vec<PathBuf> {
+    let manifest = match cargo_toml.parse::<Value>() {
+        Ok(manifest) => manifest,
+        Err(_) => return vec![],
+    };
+    ["dependencies", "build-dependencies"]
+        .iter()
+        .filter_map(|section| manifest.get(section).and_then(Value::as_table))
+        .flat_map(|dependencies| dependencies.values())
+        .filter_map(|dependency| dependency.get("path").and_then(Value::as_str))
+        .filter_map(|path| manifest_dir.join(path).canonicalize().ok())
+        .collect()
+}
+
+/// Fingerprint the sources of every path dependency in the given manifest, along with the path
+/// dependencies of those in turn. Cargo rebuilds the codegen binary whenever any of these change,
+/// so a cached binary can't be reused then either.
+pub fn path_dependencies_fingerprint(cargo_toml: &str, manifest_dir: &Path) -> Result<String> {
+    let mut dirs = BTreeSet::new();
+    let mut pending = path_dependencies(cargo_toml, manifest_dir);
+    while let Some(dir) = pending.pop() {
+        if dirs.insert(dir.clone()) {
+            if let Ok(manifest) = fs::read_to_string(dir.join("Cargo.toml")) {
+                pending.extend(path_dependencies(&manifest, &dir));
+            }
+        }
+    }
+
+    let mut fingerprint = String::new();
+    for dir in dirs {
+        for file in project_files(&dir)? {
+            let path = dir.join(&file);
+            let checksum = sha256_hex(&fs::read(&path)?);
+            fingerprint += &format!("{}\0{}\n", path.display(), checksum);
+        }
+    }
+    Ok(fingerprint)
+}
+
+/// Previously built codegen binaries, keyed by a hash of their sources.
+#[derive(Debug, Clone)]
+pub struct BinaryCache {
+    /// Where the binaries and the index are kept.
+    pub dir: PathBuf,
+    /// How many binaries to keep around. Caching is disabled if this is zero.
+    pub capacity: usize,
+}
+
+impl BinaryCache {
+    fn index_path(&self) -> PathBuf {
+        self.dir.join(CACHE_INDEX)
+    }
+
+    fn binary_path(&self, key: &str) -> PathBuf {
+        let mut path = self.dir.join(key);
+        if cfg!(windows) {
+            path.set_extension("exe");
+        }
+        path
+    }
+
+    fn read_index(&self) -> Vec<String> {
+        fs::read_to_string(self.index_path())
+            .ok()
+            .and_then(|index| serde_json::from_str(&index).ok())
+            .unwrap_or_default()
+    }
+
+    /// Write out the index with the given key at the front, and remove binaries that no longer fit.
+    fn promote(&self, key: &str) -> Result<()> {
+        let mut index = self.read_index();
+        index.retain(|k| k != key);
+        index.insert(0, key.to_owned());
+        if index.len() > self.capacity {
+            for evicted in index.split_off(self.capacity) {
+                let _ = fs::remove_file(self.binary_path(&evicted));
+            }
+        }
+        fs::write(self.index_path(), serde_json::to_string_pretty(&index)?)
+    }
+
+    /// Retrieve the cached binary for the given key, if there is one.
+    pub fn get(&self, key: &str) -> Option<PathBuf> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let path = self.binary_path(key);
+        if path.exists() {
+            // a failure to update the index only affects which binary gets evicted next
+            let _ = self.promote(key);
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Save a copy of the freshly built binary under the given key. Returns the path to the copy,
+    /// or the original path if caching is disabled.
+    pub fn store(&self, key: &str, binary: &Path) -> Result<PathBuf> {
+        if self.capacity == 0 {
+            return Ok(binary.to_path_buf());
+        }
+        fs::create_dir_all(&self.dir)?;
+        let path = self.binary_path(key);
+        fs::copy(binary, &path)?;
+        self.promote(key)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn temp_cache(capacity: usize) -> (TempDir, BinaryCache) {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = BinaryCache {
+            dir: dir.path().join("cache"),
+            capacity,
+        };
+        (dir, cache)
+    }
+
+    fn fake_binary(cache: &BinaryCache, contents: &str) -> PathBuf {
+        let path = cache.dir.with_extension("bin");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let key = cache_key("fn main() {}", "[package]", "rustc 1.48.0");
+        assert_eq!(key, cache_key("fn main() {}", "[package]", "rustc 1.48.0"));
+        assert_ne!(key, cache_key("fn main() {}", "[package]", "rustc 1.49.0"));
+        assert_ne!(key, cache_key("fn main() {}[package]", "", "rustc 1.48.0"));
+    }
+
+    #[test]
+    fn test_path_dependencies_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for crate_dir in &["yang/src", "yin/src", "yin/target", "build"] {
+            fs::create_dir_all(root.join(crate_dir)).unwrap();
+        }
+        fs::write(
+            root.join("yang/Cargo.toml"),
+            "[dependencies]\nzamm_yin = { path = \"../yin\" }\n",
+        )
+        .unwrap();
+        fs::write(root.join("yang/src/lib.rs"), "mod yang;").unwrap();
+        fs::write(root.join("yin/src/lib.rs"), "mod yin;").unwrap();
+        let cargo_toml = "[dependencies]\nzamm_yang = { path = \"../yang\" }\nserde = \"1\"\n";
+        let build_dir = root.join("build");
+        let fingerprint = || path_dependencies_fingerprint(cargo_toml, &build_dir).unwrap();
+
+        let original = fingerprint();
+        assert!(original.contains("lib.rs"));
+        fs::write(root.join("yin/target/out"), "build output").unwrap();
+        assert_eq!(fingerprint(), original);
+        // changes to transitive path dependencies count too
+        fs::write(root.join("yin/src/lib.rs"), "mod yin2;").unwrap();
+        assert_ne!(fingerprint(), original);
+
+        assert_eq!(
+            path_dependencies_fingerprint("[dependencies]\nserde = \"1\"\n", &build_dir).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_store_and_get() {
+        let (_dir, cache) = temp_cache(2);
+        assert_eq!(cache.get("a"), None);
+        let stored = cache.store("a", &fake_binary(&cache, "binary a")).unwrap();
+        assert_eq!(cache.get("a"), Some(stored.clone()));
+        assert_eq!(fs::read_to_string(stored).unwrap(), "binary a");
+    }
+
+    #[test]
+    fn test_least_recently_used_evicted() {
+        let (_dir, cache) = temp_cache(2);
+        let binary = fake_binary(&cache, "binary");
+        cache.store("a", &binary).unwrap();
+        cache.store("b", &binary).unwrap();
+        cache.get("a");
+        cache.store("c", &binary).unwrap();
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let (_dir, cache) = temp_cache(0);
+        let binary = fake_binary(&cache, "binary");
+        assert_eq!(cache.store("a", &binary).unwrap(), binary);
+        assert_eq!(cache.get("a"), None);
+        assert!(!cache.dir.exists());
+    }
+}
@@ -0,0 +1,297 @@
+use crate::build_script;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+/// Environment variable for the directory that intermediate builds put their artifacts in. Takes
+/// precedence over the project configuration.
+pub const ZAMM_TARGET_DIR_VAR: &str = "ZAMM_TARGET_DIR";
+
+/// Prefix that environment variables declared for the codegen binary must start with.
+pub const RUN_ENV_PREFIX: &str = "ZAMM_";
+
+/// Environment variable that Cargo passes compiler flags to build scripts in, separated by `\x1f`.
+/// Cargo prefers it over `RUSTFLAGS` when both are set.
+const ENCODED_RUSTFLAGS_VAR: &str = "CARGO_ENCODED_RUSTFLAGS";
+
+/// Add the extra compiler flags to the ones already in the environment, so that flags set for zamm
+/// itself carry over to the intermediate build. Returns the variable to pass them in and its value.
+fn combined_rustflags(
+    encoded: Option<&str>,
+    existing: Option<&str>,
+    extra: &str,
+) -> (&'static str, String) {
+    match (encoded, existing) {
+        (Some(encoded), _) => {
+            let mut flags: Vec<&str> = encoded.split('\x1f').filter(|f| !f.is_empty()).collect();
+            flags.extend(extra.split_whitespace());
+            (ENCODED_RUSTFLAGS_VAR, flags.join("\x1f"))
+        }
+        (None, Some(existing)) if !existing.trim().is_empty() => {
+            ("RUSTFLAGS", format!("{} {}", existing.trim(), extra))
+        }
+        (None, _) => ("RUSTFLAGS", extra.to_owned()),
+    }
+}
+
+/// Options for building the intermediate codegen binary. These can be set in the `[build]` table
+/// of the project configuration.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct BuildConfig {
+    /// How many previously built codegen binaries to keep around, so that switching back to an
+    /// earlier version of the input doesn't require a rebuild. Set to zero to always rebuild.
+    pub cache_size: usize,
+    /// Whether or not intermediate builds of different projects should share a target directory,
+    /// so that dependencies such as `zamm_yang` only get compiled once.
+    pub shared_target: bool,
+    /// The shared target directory to use instead of the one in the user cache directory.
+    pub target_dir: Option<PathBuf>,
+    /// The Cargo profile to build with, such as `release` for heavy generators. Defaults to the
+    /// `dev` profile.
+    pub profile: Option<String>,
+    /// Whether or not to build without accessing the network.
+    pub offline: bool,
+    /// Whether or not to require that `Cargo.lock` and the dependency cache are up to date.
+    pub frozen: bool,
+    /// Number of parallel jobs to build with.
+    pub jobs: Option<NonZeroU32>,
+    /// Features of the intermediate crate's dependencies to turn on.
+    pub features: Vec<String>,
+    /// Extra flags to pass to the compiler through `RUSTFLAGS`.
+    pub rustflags: Option<String>,
+    /// Any other arguments to pass to `cargo build`.
+    pub cargo_args: Vec<String>,
+    /// The template that wraps the literate code in a main function: `yang`, `yin`, `plain`, or
+    /// the path to a template file. Defaults to `yang`.
+    pub template: Option<String>,
+    /// Arguments to run the codegen binary with.
+    pub args: Vec<String>,
+    /// Extra environment variables to run the codegen binary with. Their names must start with
+    /// `ZAMM_`.
+    pub env: BTreeMap<String, String>,
+    /// Rust edition of the intermediate crate.
+    pub edition: String,
+    /// Rustup toolchain to build the codegen binary with, such as `stable` or `1.50.0`. Defaults
+    /// to whichever toolchain is active.
+    pub toolchain: Option<String>,
+    /// Where to keep the intermediate build's `Cargo.lock`, relative to the project root.
+    /// Defaults to `intermediate.lock` next to the input file.
+    pub lock_file: Option<PathBuf>,
+    /// Whether or not to let Cargo pick new dependency versions for the intermediate build, and
+    /// save them to the lock file.
+    pub update_lock: bool,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        Self {
+            cache_size: 5,
+            shared_target: true,
+            target_dir: None,
+            profile: None,
+            offline: false,
+            frozen: false,
+            jobs: None,
+            features: vec![],
+            rustflags: None,
+            cargo_args: vec![],
+            template: None,
+            args: vec![],
+            env: BTreeMap::new(),
+            edition: "2018".to_owned(),
+            toolchain: None,
+            lock_file: None,
+            update_lock: false,
+        }
+    }
+}
+
+impl BuildConfig {
+    /// The target directory for the intermediate build in the given build directory. Unless
+    /// sharing is turned off, this is the directory set by `ZAMM_TARGET_DIR`, the configured
+    /// directory, or else a directory in the user cache, in that order.
+    pub fn target_dir(&self, build_dir: &Path) -> PathBuf {
+        let local = build_dir.join("target");
+        if !self.shared_target {
+            return local;
+        }
+        build_script::rerun_if_env_changed(ZAMM_TARGET_DIR_VAR);
+        env::var_os(ZAMM_TARGET_DIR_VAR)
+            .map(PathBuf::from)
+            .or_else(|| self.target_dir.clone())
+            .or_else(|| dirs::cache_dir().map(|d| d.join("zamm").join("target")))
+            .unwrap_or(local)
+    }
+
+    /// Arguments that select the configured toolchain for `cargo` or `rustc`, to go before the
+    /// subcommand.
+    pub fn toolchain_args(&self) -> Vec<String> {
+        self.toolchain.iter().map(|t| format!("+{}", t)).collect()
+    }
+
+    /// The environment variable that passes the configured compiler flags to Cargo, and its value.
+    pub fn rustflags_env(&self) -> Option<(&'static str, String)> {
+        self.rustflags.as_ref()?;
+        Some(self.combined_rustflags())
+    }
+
+    /// Every compiler flag that the codegen binary gets built with, whether configured or
+    /// inherited from the environment, along with the variable that passes them to Cargo.
+    pub fn combined_rustflags(&self) -> (&'static str, String) {
+        let encoded = env::var(ENCODED_RUSTFLAGS_VAR).ok();
+        let existing = env::var("RUSTFLAGS").ok();
+        combined_rustflags(
+            encoded.as_deref(),
+            existing.as_deref(),
+            self.rustflags.as_deref().unwrap_or(""),
+        )
+    }
+
+    /// Arguments to pass to `cargo build`, not including the subcommand itself.
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        match self.profile.as_deref() {
+            None | Some("dev") => (),
+            Some("release") => args.push("--release".to_owned()),
+            Some(profile) => args.extend(vec!["--profile".to_owned(), profile.to_owned()]),
+        }
+        if self.offline {
+            args.push("--offline".to_owned());
+        }
+        if self.frozen {
+            args.push("--frozen".to_owned());
+        }
+        if let Some(jobs) = self.jobs {
+            args.extend(vec!["--jobs".to_owned(), jobs.to_string()]);
+        }
+        if !self.features.is_empty() {
+            args.extend(vec!["--features".to_owned(), self.features.join(",")]);
+        }
+        args.extend(self.cargo_args.iter().cloned());
+        args
+    }
+
+    /// Extra environment variables to run the codegen binary with. Fails if any of them are not
+    /// prefixed with `ZAMM_`, so that they can't clobber variables meant for something else.
+    pub fn run_env(&self) -> Result<Vec<(&str, &str)>> {
+        self.env
+            .iter()
+            .map(|(name, value)| {
+                if name.starts_with(RUN_ENV_PREFIX) {
+                    Ok((name.as_str(), value.as_str()))
+                } else {
+                    Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "Environment variable {} for the codegen binary should start with {}",
+                            name, RUN_ENV_PREFIX
+                        ),
+                    ))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_cargo_args() {
+        assert!(BuildConfig::default().cargo_args().is_empty());
+    }
+
+    #[test]
+    fn test_cargo_args() {
+        let build_cfg = BuildConfig {
+            profile: Some("release".to_owned()),
+            offline: true,
+            jobs: NonZeroU32::new(4),
+            features: vec!["zamm_yang/full".to_owned(), "extra".to_owned()],
+            cargo_args: vec!["--verbose".to_owned()],
+            ..BuildConfig::default()
+        };
+        assert_eq!(
+            build_cfg.cargo_args(),
+            vec![
+                "--release",
+                "--offline",
+                "--jobs",
+                "4",
+                "--features",
+                "zamm_yang/full,extra",
+                "--verbose"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_toolchain_args() {
+        assert!(BuildConfig::default().toolchain_args().is_empty());
+        let build_cfg = BuildConfig {
+            toolchain: Some("1.50.0".to_owned()),
+            ..BuildConfig::default()
+        };
+        assert_eq!(build_cfg.toolchain_args(), vec!["+1.50.0"]);
+    }
+
+    #[test]
+    fn test_custom_profile() {
+        let build_cfg = BuildConfig {
+            profile: Some("codegen".to_owned()),
+            ..BuildConfig::default()
+        };
+        assert_eq!(build_cfg.cargo_args(), vec!["--profile", "codegen"]);
+    }
+
+    #[test]
+    fn test_zero_jobs_rejected() {
+        assert!(toml::from_str::<BuildConfig>("jobs = 0").is_err());
+        assert_eq!(
+            toml::from_str::<BuildConfig>("jobs = 2").unwrap().jobs,
+            NonZeroU32::new(2)
+        );
+    }
+
+    #[test]
+    fn test_combined_rustflags() {
+        assert_eq!(
+            combined_rustflags(None, None, "-C target-cpu=native"),
+            ("RUSTFLAGS", "-C target-cpu=native".to_owned())
+        );
+        assert_eq!(
+            combined_rustflags(None, Some("-D warnings "), "-C target-cpu=native"),
+            ("RUSTFLAGS", "-D warnings -C target-cpu=native".to_owned())
+        );
+        assert_eq!(
+            combined_rustflags(Some("-D\x1fwarnings"), Some("ignored"), "-C opt-level=2"),
+            (
+                ENCODED_RUSTFLAGS_VAR,
+                "-D\x1fwarnings\x1f-C\x1fopt-level=2".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn test_run_env() {
+        let mut build_cfg = BuildConfig::default();
+        build_cfg
+            .env
+            .insert("ZAMM_OUTPUT_DIR".to_owned(), "out".to_owned());
+        assert_eq!(
+            build_cfg.run_env().unwrap(),
+            vec![("ZAMM_OUTPUT_DIR", "out")]
+        );
+
+        build_cfg.env.insert("PATH".to_owned(), "/bin".to_owned());
+        assert_eq!(
+            build_cfg.run_env().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+}
@@ -1,26 +1,48 @@
-use super::{CodegenConfig, MainConfig};
-use crate::commands::run_streamed_command;
+use super::binary_cache::{cache_key, path_dependencies_fingerprint, BinaryCache};
+use super::dry_run::{changed_files, copy_project, FileChange, DRY_RUN_DIR, DRY_RUN_REPORT_FILE};
+use super::lock_file::{persist_lock, seed_lock};
+use super::main_template::{
+    MainTemplate, CODEGEN_CONFIG_FORMAT, CODEGEN_CONFIG_VAR, DEFAULT_TEMPLATE,
+};
+use super::manifest::{record_manifest, MANIFEST_REPORT_FILE, MANIFEST_VAR};
+use super::rust_imports::parse_imports;
+use super::yang_settings::{skew_warnings, yang_codegen_fields};
+use super::{BuildConfig, CodegenConfig, MainConfig};
+use crate::build_script;
+use crate::checksum::sha256_hex;
+use crate::commands::{run_command, run_command_streaming_stderr, run_streamed_command_with_env};
 use crate::parse::CodeExtraction;
+use crate::project::Project;
+use crate::timings::{time, Phase};
 use crate::warn;
 use indoc::formatdoc;
 use itertools::Itertools;
 use path_abs::PathAbs;
 use std::collections::HashSet;
-use std::env;
 use std::fs;
 use std::io::{Error, ErrorKind, Result};
 use std::path::Path;
 use std::path::PathBuf;
 
-/// Directory to put build files in.
-const ZAMM_INTERMEDIATE_DIR: &str = ".zamm";
+/// Directory inside the build directory where previously built binaries are kept.
+const BINARY_CACHE_DIR: &str = "cache";
 
-/// Name for the codegen binary. Be sure to change BUILD_TOML as well when changing this.
+/// File in the build directory that the codegen settings get passed to the codegen binary through.
+const CODEGEN_CONFIG_FILE: &str = "codegen_cfg.json";
+
+/// Name for the codegen binary. Each project appends its own suffix to this.
 const CODEGEN_BINARY: &str = "intermediate-code-generator";
 
+/// Name for the codegen package and binary of the given project. Each project gets its own, so
+/// that projects sharing a target directory don't overwrite each other's binaries.
+fn codegen_package(project: &Project) -> String {
+    let project_hash = sha256_hex(project.build_dir().to_str().unwrap().as_bytes());
+    format!("{}-{}", CODEGEN_BINARY, &project_hash[..12])
+}
+
 /// File contents for the intermediate cargo.toml that is only meant for generating the actual code
 /// at the end.
-fn toml_code(dependencies: &str) -> String {
+fn toml_code(package: &str, edition: &str, dependencies: &str) -> String {
     // note that zamm_yin must be running on the same version as whatever version yang is built on,
     // *not* whatever version the user is building for, because otherwise different graphs will be
     // used and it won't be initialized properly.
@@ -29,91 +51,80 @@ fn toml_code(dependencies: &str) -> String {
     // depends on this version of yin, not the version that the user is building for.
     formatdoc! {r#"
         [package]
-        name = "intermediate-code-generator"
+        name = "{package}"
         version = "1.0.0"
-        edition = "2018"
+        edition = "{edition}"
 
         [dependencies]
+        zamm_serde_json = {{ package = "serde_json", version = "1.0" }}
         {dependencies}
-    "#, dependencies = dependencies}
+    "#, package = package, edition = edition, dependencies = dependencies}
 }
 
-/// Directory where we're outputting things.
-fn build_subdir() -> PathBuf {
-    let mut tmp = env::current_dir().unwrap();
-    tmp.push(ZAMM_INTERMEDIATE_DIR);
-    tmp
-}
-
-/// Generate code for a main function.
-pub fn code_main(main_cfg: &MainConfig, codegen_cfg: &CodegenConfig) -> String {
-    let imports = main_cfg.imports.iter().format("\n").to_string();
-    let code = main_cfg.lines.iter().format("\n").to_string();
-
-    formatdoc! {r#"
-        {imports}
-
-        fn main() {{
-            let codegen_cfg = CodegenConfig {{
-                comment_autogen: {comment_autogen},
-                add_rustfmt_attributes: {add_rustfmt_attributes},
-                track_autogen: {track_autogen},
-                yin: {yin},
-                release: {release},
-            }};
-
-            initialize_kb();
-            // ------------------------ START OF LITERATE RUST -------------------------
-        {code}
-            // -------------------------- END OF LITERATE RUST -------------------------
-            handle_all_implementations(&codegen_cfg);
-        }}
-    "#, imports = imports,
-    comment_autogen = codegen_cfg.comment_autogen,
-    add_rustfmt_attributes = codegen_cfg.add_rustfmt_attributes,
-    track_autogen = codegen_cfg.track_autogen,
-    yin = codegen_cfg.yin,
-    release = codegen_cfg.release,
-    code = code}
+/// Contents of the file that passes the codegen settings to the codegen binary.
+fn codegen_config_json(codegen_cfg: &CodegenConfig) -> String {
+    let cfg = serde_json::json!({
+        "format": CODEGEN_CONFIG_FORMAT,
+        "zamm_version": env!("CARGO_PKG_VERSION"),
+        "codegen": codegen_cfg,
+    });
+    serde_json::to_string_pretty(&cfg).unwrap()
 }
 
-/// Output code to filename
+/// Output code to filename. The file is left untouched if it already has the same contents, so that
+/// Cargo doesn't see it as changed.
 pub fn output_code_verbatim(code: &str, file_path: &str) {
     let file_pathabs = PathAbs::new(Path::new(file_path)).unwrap();
     let file_absolute = file_pathabs.as_path().to_str().unwrap();
     let file_parent = file_pathabs.as_path().parent().unwrap();
+    if fs::read_to_string(file_absolute).ok().as_deref() == Some(code) {
+        return;
+    }
     fs::create_dir_all(file_parent).unwrap();
     fs::write(file_absolute, code)
         .unwrap_or_else(|_| panic!("Couldn't output generated code to {}", file_absolute));
 }
 
 /// Write code for the main function to a file.
-fn output_main(main_cfg: &MainConfig, codegen_cfg: &CodegenConfig) {
-    let mut main_rs = build_subdir();
+fn output_main(build_dir: &Path, main_code: &str) {
+    let mut main_rs = build_dir.to_path_buf();
     main_rs.push("src/main.rs");
-    output_code_verbatim(
-        &code_main(main_cfg, codegen_cfg),
-        &main_rs.to_str().unwrap(),
-    );
+    output_code_verbatim(main_code, &main_rs.to_str().unwrap());
+}
+
+/// Write the codegen settings for the codegen binary to read. Returns the path to the settings.
+fn output_codegen_config(build_dir: &Path, codegen_cfg: &CodegenConfig) -> String {
+    let mut cfg_file = build_dir.to_path_buf();
+    cfg_file.push(CODEGEN_CONFIG_FILE);
+    let cfg_path = cfg_file.to_str().unwrap();
+    output_code_verbatim(&codegen_config_json(codegen_cfg), cfg_path);
+    cfg_path.to_owned()
 }
 
 /// Write the cargo.toml
-fn output_cargo_toml(dependencies: &str) {
-    let mut cargo_toml = build_subdir();
+fn output_cargo_toml(build_dir: &Path, dependencies: &str) {
+    let mut cargo_toml = build_dir.to_path_buf();
     cargo_toml.push("Cargo.toml"); // Cargo files are somehow uppercased by default
     output_code_verbatim(dependencies, &cargo_toml.to_str().unwrap());
 }
 
 /// Set up the build directory for compilation of a program that will then go on to generate the
 /// final code files.
-fn output_build_dir(code: &CodeExtraction, codegen_cfg: &CodegenConfig) {
-    output_main(&separate_imports(&code.rust), codegen_cfg);
-    output_cargo_toml(&toml_code(&code.toml));
+fn output_build_dir(build_dir: &Path, main_code: &str, cargo_toml: &str) {
+    output_main(build_dir, main_code);
+    output_cargo_toml(build_dir, cargo_toml);
     println!("Finished generating codegen files.");
 }
 
-/// Separate imports embedded in the code, similar to how `rustdoc` does it.
-fn separate_imports(code: &str) -> MainConfig {
+/// Version of the Rust toolchain that the codegen binary will be built with.
+fn toolchain_version(build_cfg: &BuildConfig) -> String {
+    let mut args = build_cfg.toolchain_args();
+    args.push("-vV".to_owned());
+    run_command("rustc", &args).unwrap_or_else(|_| "unknown".to_owned())
+}
+
+/// Separate imports line by line, for code that can't be parsed as Rust.
+fn separate_imports_by_line(code: &str) -> MainConfig {
     let mut import_set = HashSet::new();
     let mut lines = vec![];
     for line in code.split('\n') {
@@ -143,57 +154,415 @@ fn separate_imports(code: &str) -> MainConfig {
     }
 }
 
-/// Builds the codegen binary, and returns the path to said binary.
-fn build_codegen_binary() -> Result<String> {
-    let src_dir = env::current_dir().unwrap();
-    let subdir = build_subdir();
-    env::set_current_dir(&subdir).unwrap();
+/// Separate imports embedded in the code, similar to how `rustdoc` does it.
+fn separate_imports(code: &str) -> MainConfig {
+    parse_imports(code).unwrap_or_else(|| {
+        warn!(
+            "Could not parse the literate code as Rust, separating imports line by line instead."
+        );
+        separate_imports_by_line(code)
+    })
+}
+
+/// Find the path to the package's binary among the JSON messages that Cargo outputs.
+fn find_executable(cargo_messages: &str, package: &str) -> Option<PathBuf> {
+    cargo_messages
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|message| {
+            message["reason"] == "compiler-artifact" && message["target"]["name"] == package
+        })
+        .filter_map(|message| message["executable"].as_str().map(PathBuf::from))
+        .next_back()
+}
+
+/// Builds the codegen binary, and returns the path to said binary. When the target directory is
+/// shared with other projects, Cargo's own lock on the target directory keeps concurrent builds
+/// from stepping on each other. If the build directory was seeded from a persisted lock file, Cargo
+/// is not allowed to change it.
+fn build_codegen_binary(
+    project: &Project,
+    package: &str,
+    build_cfg: &BuildConfig,
+    seeded_from: Option<&Path>,
+) -> Result<String> {
+    let subdir = project.build_dir();
+    let target_dir = build_cfg.target_dir(&subdir);
 
     println!(
         "Now building codegen binary in {} ...",
         subdir.to_str().unwrap()
     );
-    run_streamed_command("cargo", vec!["build"])?;
+    let mut args = build_cfg.toolchain_args();
+    args.push("build".to_owned());
+    args.push("--message-format=json-render-diagnostics".to_owned());
+    args.extend(build_cfg.cargo_args());
+    if seeded_from.is_some() && !build_cfg.frozen {
+        args.push("--locked".to_owned());
+    }
+    let mut envs = vec![("CARGO_TARGET_DIR", target_dir.to_str().unwrap())];
+    let rustflags = build_cfg.rustflags_env();
+    if let Some((var, flags)) = &rustflags {
+        envs.push((var, flags));
+    }
+    let cargo_messages = run_command_streaming_stderr("cargo", &args, &envs, &subdir).map_err(
+        |e| match seeded_from {
+            Some(lock_file) => Error::new(
+                e.kind(),
+                format!(
+                    "{}\n{} may be out of date with the dependencies of the input file. Build \
+                    with --update-lock to update it.",
+                    e,
+                    lock_file.display()
+                ),
+            ),
+            None => e,
+        },
+    )?;
 
     // Verify successful build
-    let mut binary = subdir;
-    binary.push(format!("target/debug/{}", CODEGEN_BINARY));
-    if cfg!(windows) {
-        binary.set_extension("exe");
-    }
+    let binary = find_executable(&cargo_messages, package).ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("Cargo did not report building a binary for {}", package),
+        )
+    })?;
     let binary_path = binary.to_str().unwrap();
     if !binary.exists() {
         return Err(Error::new(
             ErrorKind::NotFound,
             format!(
-                "Codegen binary was not found at expected location {}",
+                "Codegen binary was not found at reported location {}",
                 binary_path
             ),
         ));
     }
     println!("Binary successfully built at {}", binary_path);
-    println!(
-        "Returning to {} and running codegen...",
-        src_dir.to_str().unwrap()
-    );
-    env::set_current_dir(&src_dir).unwrap();
 
     Ok(binary_path.to_owned())
 }
 
-/// Generate code using the specified code and imports, and runs the binary.
-pub fn generate_final_code(code: &CodeExtraction, codegen_cfg: &CodegenConfig) -> Result<()> {
-    output_build_dir(code, codegen_cfg);
-    let binary_path = build_codegen_binary()?;
+/// Sources for the codegen binary.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IntermediateSources {
+    /// Name of the codegen package.
+    pub package: String,
+    /// Contents of the codegen binary's `main.rs`.
+    pub main_rs: String,
+    /// Contents of the codegen binary's `Cargo.toml`.
+    pub cargo_toml: String,
+}
+
+/// Generate the sources for a codegen binary that runs the specified code and imports. Template
+/// files are taken to be relative to the project root. For the Yang template, Cargo gets asked for
+/// the version of Yang that the code depends on, so that only the codegen settings that Yang has
+/// are passed on to it.
+pub fn intermediate_sources(
+    project: &Project,
+    code: &CodeExtraction,
+    build_cfg: &BuildConfig,
+) -> Result<IntermediateSources> {
+    let package = codegen_package(project);
+    let template =
+        match MainTemplate::from_name(build_cfg.template.as_deref().unwrap_or(DEFAULT_TEMPLATE)) {
+            MainTemplate::File(path) => {
+                let path = project.path(path);
+                build_script::rerun_if_changed(&path);
+                MainTemplate::File(path)
+            }
+            template => template,
+        };
+    let cargo_toml = toml_code(&package, &build_cfg.edition, &code.toml);
+    let yang_fields = if template == MainTemplate::Yang {
+        let build_dir = project.build_dir();
+        output_cargo_toml(&build_dir, &cargo_toml);
+        // the build directory only has the persisted lock file in it if it's not being updated
+        let locked = match &build_cfg.lock_file {
+            Some(lock_file) => !build_cfg.update_lock && project.path(lock_file).exists(),
+            None => false,
+        };
+        yang_codegen_fields(&build_dir, build_cfg, locked)
+    } else {
+        None
+    };
+    for warning in yang_fields.iter().flat_map(|f| skew_warnings(f)) {
+        warn!("{}", warning);
+    }
+    Ok(IntermediateSources {
+        main_rs: template.render_for_yang(
+            &separate_imports(&(template.imported_code(&code.imported_rust) + &code.rust)),
+            yang_fields.as_deref(),
+        )?,
+        cargo_toml,
+        package,
+    })
+}
+
+/// Build the codegen binary for the specified code and imports, and return the path to it. If a
+/// binary was already built from the exact same code and dependency versions, it is reused instead
+/// of being built again. The build is held to the dependency versions in the configured lock file,
+/// and saves them there if there were none yet and `persist` is set.
+fn codegen_binary(
+    project: &Project,
+    code: &CodeExtraction,
+    build_cfg: &BuildConfig,
+    persist: bool,
+) -> Result<String> {
+    // seeded first, so that Cargo looks up Yang's version from the persisted lock file
+    let lock_file = build_cfg.lock_file.as_ref().map(|l| project.path(l));
+    let seeded = match &lock_file {
+        Some(lock_file) => seed_lock(&project.build_dir(), lock_file, build_cfg.update_lock)?,
+        None => None,
+    };
+    let IntermediateSources {
+        package,
+        main_rs: main_code,
+        cargo_toml,
+    } = time(Phase::BuildDir, || {
+        intermediate_sources(project, code, build_cfg)
+    })?;
+    let cache = BinaryCache {
+        dir: project.build_dir().join(BINARY_CACHE_DIR),
+        capacity: build_cfg.cache_size,
+    };
+    // the same sources built differently, or against different local dependencies, make for a
+    // different binary
+    let build_settings = format!(
+        "{}\n{}\n{}\n{}\n{}",
+        toolchain_version(build_cfg),
+        build_cfg.cargo_args().join(" "),
+        build_cfg.combined_rustflags().1,
+        seeded.as_deref().unwrap_or(""),
+        path_dependencies_fingerprint(&cargo_toml, &project.build_dir())?
+    );
+    let key = cache_key(&main_code, &cargo_toml, &build_settings);
+
+    // updating the lock file requires Cargo to actually resolve dependencies again
+    let cached = if build_cfg.update_lock {
+        None
+    } else {
+        cache.get(&key)
+    };
+    match cached {
+        Some(cached) => {
+            let cached_path = cached.to_str().unwrap().to_owned();
+            println!("Reusing codegen binary at {}", cached_path);
+            Ok(cached_path)
+        }
+        None => {
+            time(Phase::BuildDir, || {
+                output_build_dir(&project.build_dir(), &main_code, &cargo_toml)
+            });
+            let built = time(Phase::CargoBuild, || {
+                build_codegen_binary(
+                    project,
+                    &package,
+                    build_cfg,
+                    lock_file.as_deref().filter(|_| seeded.is_some()),
+                )
+            })?;
+            if let (Some(lock_file), true, None) = (&lock_file, persist, &seeded) {
+                persist_lock(&project.build_dir(), lock_file)?;
+            }
+            let stored = cache.store(&key, Path::new(&built))?;
+            Ok(stored.to_str().unwrap().to_owned())
+        }
+    }
+}
+
+/// Run the codegen binary in the given directory, and have it report the files it generates to
+/// `report_path`.
+fn run_codegen_binary(
+    project: &Project,
+    binary_path: &str,
+    run_dir: &Path,
+    report_path: &Path,
+    codegen_cfg: &CodegenConfig,
+    build_cfg: &BuildConfig,
+) -> Result<()> {
+    let mut envs = build_cfg.run_env()?;
+    let cfg_path = output_codegen_config(&project.build_dir(), codegen_cfg);
+    if report_path.exists() {
+        fs::remove_file(report_path)?;
+    }
     println!("==================== RUNNING CODEGEN ====================");
-    run_streamed_command(&binary_path, Vec::<&str>::new())
+    envs.push((CODEGEN_CONFIG_VAR, &cfg_path));
+    envs.push((MANIFEST_VAR, report_path.to_str().unwrap()));
+    time(Phase::Codegen, || {
+        run_streamed_command_with_env(binary_path, &build_cfg.args, &envs, run_dir)
+    })
+}
+
+/// Generate code using the specified code and imports, and runs the binary in the project root. If
+/// a binary was already built from the exact same code, it is reused instead of being built again.
+/// Whatever files the binary reports generating get recorded in the project's manifest.
+pub fn generate_final_code(
+    project: &Project,
+    code: &CodeExtraction,
+    codegen_cfg: &CodegenConfig,
+    build_cfg: &BuildConfig,
+) -> Result<()> {
+    // fail on invalid settings before spending time on a build
+    build_cfg.run_env()?;
+    let binary_path = codegen_binary(project, code, build_cfg, true)?;
+    let report_path = project.build_dir().join(MANIFEST_REPORT_FILE);
+    run_codegen_binary(
+        project,
+        &binary_path,
+        &project.root,
+        &report_path,
+        codegen_cfg,
+        build_cfg,
+    )?;
+    time(Phase::PostProcess, || {
+        record_manifest(project, &report_path)
+    })
+}
+
+/// Same as `generate_final_code`, except that the binary is run in a scratch copy of the project.
+/// Returns every file that would change, and leaves the project itself untouched.
+pub fn dry_run_final_code(
+    project: &Project,
+    code: &CodeExtraction,
+    codegen_cfg: &CodegenConfig,
+    build_cfg: &BuildConfig,
+) -> Result<Vec<FileChange>> {
+    build_cfg.run_env()?;
+    // the lock file is part of the project, so it's left alone as well
+    let binary_path = codegen_binary(project, code, build_cfg, false)?;
+    let scratch = project.build_dir().join(DRY_RUN_DIR);
+    let report_path = project.build_dir().join(DRY_RUN_REPORT_FILE);
+    if scratch.exists() {
+        fs::remove_dir_all(&scratch)?;
+    }
+    time(Phase::BuildDir, || copy_project(&project.root, &scratch))?;
+    let changes = run_codegen_binary(
+        project,
+        &binary_path,
+        &scratch,
+        &report_path,
+        codegen_cfg,
+        build_cfg,
+    )
+    .and_then(|_| {
+        time(Phase::PostProcess, || {
+            changed_files(&project.root, &scratch)
+        })
+    });
+    fs::remove_dir_all(&scratch)?;
+    changes
+}
+
+/// Remove everything that Cargo built for the given package from the target directory, leaving the
+/// artifacts of other packages alone.
+fn remove_package_artifacts(target_dir: &Path, package: &str) -> Result<()> {
+    // library and binary artifacts use underscores where the package name has dashes
+    let prefixes = [package.to_owned(), package.replace('-', "_")];
+    for profile in fs::read_dir(target_dir)?.filter_map(|e| e.ok()) {
+        let profile_dir = profile.path();
+        let artifact_dirs = ["", "deps", ".fingerprint", "build", "incremental"]
+            .iter()
+            .map(|d| profile_dir.join(d))
+            .filter(|d| d.is_dir());
+        for artifact_dir in artifact_dirs {
+            for entry in fs::read_dir(&artifact_dir)?.filter_map(|e| e.ok()) {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if !prefixes.iter().any(|p| name.starts_with(p.as_str())) {
+                    continue;
+                }
+                let path = entry.path();
+                if path.is_dir() {
+                    fs::remove_dir_all(path)?;
+                } else {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove this project's intermediate build artifacts from the shared target directory, if there
+/// is one. Artifacts in the project's own build directory are left for the caller to remove.
+pub fn clean_shared_target(project: &Project, build_cfg: &BuildConfig) -> Result<()> {
+    let subdir = project.build_dir();
+    let target_dir = build_cfg.target_dir(&subdir);
+    if target_dir.starts_with(&subdir) || !target_dir.is_dir() {
+        return Ok(());
+    }
+    remove_package_artifacts(&target_dir, &codegen_package(project))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::intermediate_build::{Manifest, INTERMEDIATE_LOCK_FILE};
     use indoc::indoc;
 
+    #[test]
+    fn test_find_executable() {
+        let messages = indoc! {r#"
+            {"reason":"compiler-artifact","target":{"name":"zamm_yang","kind":["lib"]},"executable":null}
+            not json
+            {"reason":"compiler-artifact","target":{"name":"intermediate-code-generator-abc","kind":["bin"]},"executable":"/tmp/target/release/intermediate-code-generator-abc"}
+            {"reason":"build-finished","success":true}
+        "#};
+        assert_eq!(
+            find_executable(messages, "intermediate-code-generator-abc"),
+            Some(PathBuf::from(
+                "/tmp/target/release/intermediate-code-generator-abc"
+            ))
+        );
+        assert_eq!(find_executable(messages, "other"), None);
+    }
+
+    #[test]
+    fn test_remove_package_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path();
+        let package = "intermediate-code-generator-abc";
+        let other = "intermediate-code-generator-def";
+        let artifacts = [
+            format!("debug/{}", package),
+            format!("debug/deps/{}-1234", package.replace('-', "_")),
+            format!("debug/.fingerprint/{}-1234/bin", package),
+            format!("release/{}", package),
+        ];
+        let others = [
+            format!("debug/{}", other),
+            "debug/deps/zamm_yang-1234".to_owned(),
+        ];
+        for file in artifacts.iter().chain(others.iter()) {
+            let path = target.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        remove_package_artifacts(target, package).unwrap();
+        for file in &artifacts {
+            assert!(!target.join(file).exists(), "{} still exists", file);
+        }
+        assert!(!target
+            .join(format!("debug/.fingerprint/{}-1234", package))
+            .exists());
+        for file in &others {
+            assert!(target.join(file).exists(), "{} was removed", file);
+        }
+    }
+
+    #[test]
+    fn test_codegen_config_json() {
+        let cfg: serde_json::Value = serde_json::from_str(&codegen_config_json(&CodegenConfig {
+            yin: true,
+            ..CodegenConfig::default()
+        }))
+        .unwrap();
+        assert_eq!(cfg["format"], CODEGEN_CONFIG_FORMAT);
+        assert_eq!(cfg["codegen"]["yin"], true);
+        assert_eq!(cfg["codegen"]["comment_autogen"], true);
+    }
+
     #[test]
     fn test_separate_imports_empty() {
         assert_eq!(
@@ -271,4 +640,156 @@ mod tests {
             }
         );
     }
+
+    /// Stand-in for Yang that generates a single file, marked as autogenerated unless told
+    /// otherwise, and only lists it when tracking.
+    const FAKE_YANG: &str = r#"
+        pub mod helper {
+            pub fn start_imports() {}
+            pub fn end_imports() {}
+        }
+
+        pub mod prelude {
+            #[derive(Default)]
+            pub struct CodegenConfig {
+                pub comment_autogen: bool,
+                pub add_rustfmt_attributes: bool,
+                pub track_autogen: bool,
+                pub yin: bool,
+                pub release: bool,
+            }
+
+            pub fn initialize_kb() {}
+
+            pub fn handle_all_implementations(cfg: &CodegenConfig) {
+                std::fs::create_dir_all("src").unwrap();
+                let marker = if cfg.comment_autogen { "// autogenerated\n" } else { "" };
+                std::fs::write("src/tao.rs", format!("{}pub struct Tao;\n", marker)).unwrap();
+                if cfg.track_autogen {
+                    std::fs::write(".autogen.txt", "src/tao.rs\n").unwrap();
+                }
+            }
+        }
+    "#;
+
+    /// A project whose literate code generates code with the fake Yang above.
+    fn fake_yang_project(dir: &Path) -> (Project, CodeExtraction, BuildConfig) {
+        let yang_dir = dir.join("fake_yang");
+        fs::create_dir_all(yang_dir.join("src")).unwrap();
+        fs::write(
+            yang_dir.join("Cargo.toml"),
+            "[package]\nname = \"zamm_yang\"\nversion = \"0.1.0\"\nedition = \"2018\"\n",
+        )
+        .unwrap();
+        fs::write(yang_dir.join("src/lib.rs"), FAKE_YANG).unwrap();
+
+        let project = Project::new(dir.join("project")).unwrap();
+        fs::create_dir_all(&project.root).unwrap();
+        let code = CodeExtraction {
+            rust: "use zamm_yang::prelude::*;".to_owned(),
+            toml: format!("zamm_yang = {{ path = {:?} }}", yang_dir.to_str().unwrap()),
+            ..CodeExtraction::default()
+        };
+        let build_cfg = BuildConfig {
+            shared_target: false,
+            offline: true,
+            ..BuildConfig::default()
+        };
+        (project, code, build_cfg)
+    }
+
+    #[test]
+    fn test_yang_build_reports_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let (project, mut code, build_cfg) = fake_yang_project(dir.path());
+        code.rust += "\nstd::fs::write(\"notes.md\", \"# Notes\").unwrap();\n\
+            zamm_manifest::record(\"notes.md\", \"Notes\");";
+        let paths = |manifest: &Manifest| -> Vec<PathBuf> {
+            manifest.files.iter().map(|f| f.path.clone()).collect()
+        };
+
+        // files that Yang tracks are reported without a source
+        let tracking_cfg = CodegenConfig {
+            track_autogen: true,
+            ..CodegenConfig::default()
+        };
+        generate_final_code(&project, &code, &tracking_cfg, &build_cfg).unwrap();
+        let manifest = Manifest::load(&project).unwrap().unwrap();
+        assert_eq!(
+            paths(&manifest),
+            vec![PathBuf::from("notes.md"), PathBuf::from("src/tao.rs")]
+        );
+        assert_eq!(manifest.files[1].source, None);
+
+        // without tracking, only what the literate code records is reported, even though the
+        // tracker from the last build is still around
+        generate_final_code(&project, &code, &CodegenConfig::default(), &build_cfg).unwrap();
+        let manifest = Manifest::load(&project).unwrap().unwrap();
+        assert_eq!(paths(&manifest), vec![PathBuf::from("notes.md")]);
+        assert_eq!(manifest.files[0].source.as_deref(), Some("Notes"));
+    }
+
+    #[test]
+    fn test_check_after_release_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let (project, code, build_cfg) = fake_yang_project(dir.path());
+        let release_cfg = CodegenConfig::release(false);
+        generate_final_code(&project, &code, &release_cfg, &build_cfg).unwrap();
+
+        let changes = dry_run_final_code(&project, &code, &release_cfg, &build_cfg).unwrap();
+        assert_eq!(changes, vec![]);
+        // a regular build would mark the generated code
+        let changes =
+            dry_run_final_code(&project, &code, &CodegenConfig::default(), &build_cfg).unwrap();
+        let paths: Vec<&Path> = changes.iter().map(|c| c.path.as_path()).collect();
+        assert_eq!(paths, vec![Path::new("src/tao.rs")]);
+    }
+
+    #[test]
+    fn test_stale_lock_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (project, mut code, mut build_cfg) = fake_yang_project(dir.path());
+        build_cfg.lock_file = Some(PathBuf::from(INTERMEDIATE_LOCK_FILE));
+        let codegen_cfg = CodegenConfig::default();
+        generate_final_code(&project, &code, &codegen_cfg, &build_cfg).unwrap();
+        let lock_file = project.path(INTERMEDIATE_LOCK_FILE);
+        let saved = fs::read_to_string(&lock_file).unwrap();
+
+        let extra_dir = dir.path().join("extra");
+        fs::create_dir_all(extra_dir.join("src")).unwrap();
+        fs::write(
+            extra_dir.join("Cargo.toml"),
+            "[package]\nname = \"extra\"\nversion = \"0.1.0\"\n",
+        )
+        .unwrap();
+        fs::write(extra_dir.join("src/lib.rs"), "").unwrap();
+        code.toml += &format!("\nextra = {{ path = {:?} }}", extra_dir.to_str().unwrap());
+        let error = generate_final_code(&project, &code, &codegen_cfg, &build_cfg).unwrap_err();
+        assert!(error.to_string().contains("--update-lock"));
+        assert_eq!(fs::read_to_string(&lock_file).unwrap(), saved);
+
+        build_cfg.update_lock = true;
+        generate_final_code(&project, &code, &codegen_cfg, &build_cfg).unwrap();
+        assert!(fs::read_to_string(&lock_file).unwrap().contains("extra"));
+    }
+
+    #[test]
+    fn test_build_against_other_yang_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let (project, code, build_cfg) = fake_yang_project(dir.path());
+        // a Yang that has dropped one setting and added another
+        let skewed = FAKE_YANG.replace("pub release: bool,", "pub parallel: bool,");
+        assert_ne!(skewed, FAKE_YANG);
+        fs::write(dir.path().join("fake_yang/src/lib.rs"), skewed).unwrap();
+
+        let main_rs = intermediate_sources(&project, &code, &build_cfg)
+            .unwrap()
+            .main_rs;
+        assert!(!main_rs.contains("codegen_cfg.release"));
+        generate_final_code(&project, &code, &CodegenConfig::release(false), &build_cfg).unwrap();
+        assert_eq!(
+            fs::read_to_string(project.path("src/tao.rs")).unwrap(),
+            "pub struct Tao;\n"
+        );
+    }
 }
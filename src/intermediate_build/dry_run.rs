@@ -0,0 +1,194 @@
+use crate::project::ZAMM_INTERMEDIATE_DIR;
+use similar::{ChangeTag, TextDiff};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// Directory inside the build directory where dry runs get a scratch copy of the project.
+pub const DRY_RUN_DIR: &str = "dry-run";
+
+/// File in the build directory that the codegen binary reports its generated files to during a dry
+/// run, so that the report for the last real build is left alone.
+pub const DRY_RUN_REPORT_FILE: &str = "dry-run-generated.json";
+
+/// Directories that are never copied into or compared against the scratch copy of the project.
+const SKIPPED_DIRS: &[&str] = &[".git", "target", ZAMM_INTERMEDIATE_DIR];
+
+/// A file that a build would create, modify, or delete.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileChange {
+    /// Path to the file, relative to the project root.
+    pub path: PathBuf,
+    /// Contents of the file currently on disk, if it exists.
+    pub before: Option<Vec<u8>>,
+    /// Contents of the file after the build, if it still exists.
+    pub after: Option<Vec<u8>>,
+}
+
+impl FileChange {
+    /// Unified diff between the file on disk and what the build would turn it into.
+    pub fn diff(&self) -> String {
+        let path = self.path.to_str().unwrap();
+        let empty = vec![];
+        let before = std::str::from_utf8(self.before.as_ref().unwrap_or(&empty));
+        let after = std::str::from_utf8(self.after.as_ref().unwrap_or(&empty));
+        match (before, after) {
+            (Ok(before), Ok(after)) => {
+                let old_name = match self.before {
+                    Some(_) => format!("a/{}", path),
+                    None => "/dev/null".to_owned(),
+                };
+                let new_name = match self.after {
+                    Some(_) => format!("b/{}", path),
+                    None => "/dev/null".to_owned(),
+                };
+                TextDiff::from_lines(before, after)
+                    .unified_diff()
+                    .header(&old_name, &new_name)
+                    .to_string()
+            }
+            _ => format!("Binary file {} differs\n", path),
+        }
+    }
+
+    /// One-line summary of the change, with the number of lines added and removed.
+    pub fn summary(&self) -> String {
+        let path = self.path.display();
+        let (before, after) = match (&self.before, &self.after) {
+            (None, _) => return format!("{} (new file)", path),
+            (_, None) => return format!("{} (deleted)", path),
+            (Some(before), Some(after)) => (before, after),
+        };
+        match (std::str::from_utf8(before), std::str::from_utf8(after)) {
+            (Ok(before), Ok(after)) => {
+                let diff = TextDiff::from_lines(before, after);
+                let count = |tag| diff.iter_all_changes().filter(|c| c.tag() == tag).count();
+                format!(
+                    "{} (+{} -{})",
+                    path,
+                    count(ChangeTag::Insert),
+                    count(ChangeTag::Delete)
+                )
+            }
+            _ => format!("{} (binary)", path),
+        }
+    }
+}
+
+/// Whether or not the given entry of the project root is left out of dry runs.
+fn is_skipped(relative: &Path) -> bool {
+    SKIPPED_DIRS.iter().any(|d| relative == Path::new(d))
+}
+
+/// All files in the project, relative to the project root.
+pub fn project_files(root: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let relative = dir.join(entry?.file_name());
+            if is_skipped(&relative) {
+                continue;
+            }
+            if fs::metadata(root.join(&relative))?.is_dir() {
+                dirs.push(relative);
+            } else {
+                files.insert(relative);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Copy the project to a scratch directory, leaving out version control, build outputs, and ZAMM's
+/// own build directory.
+pub fn copy_project(root: &Path, scratch: &Path) -> Result<()> {
+    for file in project_files(root)? {
+        let destination = scratch.join(&file);
+        fs::create_dir_all(destination.parent().unwrap())?;
+        fs::copy(root.join(&file), destination)?;
+    }
+    Ok(())
+}
+
+/// Every file that differs between the project and its scratch copy, sorted by path.
+pub fn changed_files(root: &Path, scratch: &Path) -> Result<Vec<FileChange>> {
+    let mut paths = project_files(root)?;
+    paths.extend(project_files(scratch)?);
+    let mut changes = vec![];
+    for path in paths {
+        let before = fs::read(root.join(&path)).ok();
+        let after = fs::read(scratch.join(&path)).ok();
+        if before != after {
+            changes.push(FileChange {
+                path,
+                before,
+                after,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_files() {
+        let root_dir = tempfile::tempdir().unwrap();
+        let scratch_dir = tempfile::tempdir().unwrap();
+        let root = root_dir.path();
+        let scratch = scratch_dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join(".zamm")).unwrap();
+        fs::write(root.join("src/lib.rs"), "mod a;\n").unwrap();
+        fs::write(root.join("src/old.rs"), "fn old() {}\n").unwrap();
+        fs::write(root.join(".zamm/main.rs"), "fn main() {}\n").unwrap();
+
+        copy_project(root, scratch).unwrap();
+        assert!(!scratch.join(".zamm").exists());
+        assert!(changed_files(root, scratch).unwrap().is_empty());
+
+        fs::write(scratch.join("src/lib.rs"), "mod a;\nmod b;\n").unwrap();
+        fs::write(scratch.join("src/b.rs"), "fn b() {}\n").unwrap();
+        fs::remove_file(scratch.join("src/old.rs")).unwrap();
+        let changes = changed_files(root, scratch).unwrap();
+        let paths: Vec<&Path> = changes.iter().map(|c| c.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("src/b.rs"),
+                Path::new("src/lib.rs"),
+                Path::new("src/old.rs")
+            ]
+        );
+        assert!(changes[0]
+            .diff()
+            .starts_with("--- /dev/null\n+++ b/src/b.rs\n"));
+        assert!(changes[1].diff().contains("\n+mod b;\n"));
+        assert!(changes[2]
+            .diff()
+            .starts_with("--- a/src/old.rs\n+++ /dev/null\n"));
+        let summaries: Vec<String> = changes.iter().map(FileChange::summary).collect();
+        assert_eq!(
+            summaries,
+            vec![
+                "src/b.rs (new file)",
+                "src/lib.rs (+1 -0)",
+                "src/old.rs (deleted)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_binary_diff() {
+        let change = FileChange {
+            path: PathBuf::from("logo.png"),
+            before: Some(vec![0xff, 0xfe]),
+            after: None,
+        };
+        assert_eq!(change.diff(), "Binary file logo.png differs\n");
+    }
+}
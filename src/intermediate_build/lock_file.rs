@@ -0,0 +1,84 @@
+use crate::build_script;
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+
+/// Default name of the file that the intermediate build's `Cargo.lock` is kept in, next to the
+/// input file.
+pub const INTERMEDIATE_LOCK_FILE: &str = "intermediate.lock";
+
+/// Name of the lock file that Cargo uses in the build directory.
+const CARGO_LOCK: &str = "Cargo.lock";
+
+/// Copy the persisted lock file into the build directory, so that Cargo builds with the same
+/// dependency versions as before. When updating, the build directory's lock file gets removed
+/// instead, so that Cargo picks the latest versions. Returns the contents of the lock file that
+/// was seeded, if any.
+pub fn seed_lock(build_dir: &Path, lock_file: &Path, update: bool) -> Result<Option<String>> {
+    let build_lock = build_dir.join(CARGO_LOCK);
+    if !update {
+        build_script::rerun_if_changed(lock_file);
+    }
+    if update || !lock_file.exists() {
+        if build_lock.exists() {
+            fs::remove_file(&build_lock)?;
+        }
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(lock_file)?;
+    fs::create_dir_all(build_dir)?;
+    fs::write(&build_lock, &contents)?;
+    Ok(Some(contents))
+}
+
+/// Save the build directory's lock file after a build that wasn't seeded from a persisted lock
+/// file. Seeded builds are run with `--locked` instead, so that the lock file only ever gets updated
+/// deliberately.
+pub fn persist_lock(build_dir: &Path, lock_file: &Path) -> Result<()> {
+    let built = match fs::read_to_string(build_dir.join(CARGO_LOCK)) {
+        Ok(built) => built,
+        Err(_) => return Ok(()), // nothing to persist
+    };
+    fs::write(lock_file, built)?;
+    println!(
+        "Saved intermediate dependency versions to {}",
+        lock_file.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let build_dir = root.join(".zamm");
+        let lock_file = root.join(INTERMEDIATE_LOCK_FILE);
+        fs::create_dir_all(&build_dir).unwrap();
+
+        // first build saves whatever Cargo picked
+        assert_eq!(seed_lock(&build_dir, &lock_file, false).unwrap(), None);
+        fs::write(build_dir.join(CARGO_LOCK), "version 1").unwrap();
+        persist_lock(&build_dir, &lock_file).unwrap();
+        assert_eq!(fs::read_to_string(&lock_file).unwrap(), "version 1");
+
+        // later builds start from the saved versions
+        fs::write(build_dir.join(CARGO_LOCK), "version 2").unwrap();
+        let seeded = seed_lock(&build_dir, &lock_file, false).unwrap();
+        assert_eq!(seeded.as_deref(), Some("version 1"));
+        assert_eq!(
+            fs::read_to_string(build_dir.join(CARGO_LOCK)).unwrap(),
+            "version 1"
+        );
+
+        // updating lets Cargo start over
+        assert_eq!(seed_lock(&build_dir, &lock_file, true).unwrap(), None);
+        assert!(!build_dir.join(CARGO_LOCK).exists());
+        fs::write(build_dir.join(CARGO_LOCK), "version 3").unwrap();
+        persist_lock(&build_dir, &lock_file).unwrap();
+        assert_eq!(fs::read_to_string(&lock_file).unwrap(), "version 3");
+    }
+}
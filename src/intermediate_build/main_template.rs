@@ -0,0 +1,451 @@
+use super::manifest::{MANIFEST_FORMAT, MANIFEST_VAR};
+use super::{MainConfig, CODEGEN_SETTINGS};
+use crate::commands::AUTOGEN_TRACKER;
+use indoc::formatdoc;
+use itertools::Itertools;
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+/// Environment variable that tells the codegen binary where to find its settings.
+pub const CODEGEN_CONFIG_VAR: &str = "ZAMM_CODEGEN_CONFIG";
+
+/// Version of the format that codegen settings are passed in. Bump this whenever the generated
+/// main function would interpret the settings file differently.
+pub const CODEGEN_CONFIG_FORMAT: u32 = 1;
+
+/// Name of the template to use when none is specified.
+pub const DEFAULT_TEMPLATE: &str = "yang";
+
+/// Placeholder in template files for the imports of the literate code.
+const IMPORTS_PLACEHOLDER: &str = "{{imports}}";
+
+/// Placeholder in template files for the literate code itself.
+const CODE_PLACEHOLDER: &str = "{{code}}";
+
+/// Placeholder in template files for the name of the environment variable pointing to the codegen
+/// settings.
+const CODEGEN_CONFIG_VAR_PLACEHOLDER: &str = "{{codegen_config_var}}";
+
+/// Placeholder in template files for the accessors of the arguments and environment variables
+/// passed to the codegen binary.
+const PARAMS_PLACEHOLDER: &str = "{{params}}";
+
+/// Accessors for the arguments and environment variables that `zamm build` passes to the codegen
+/// binary, so that the literate code can be parameterized.
+const PARAMS_MODULE: &str = r#"/// Arguments and environment variables passed in by `zamm build`.
+#[allow(dead_code)]
+mod zamm_params {
+    use std::str::FromStr;
+
+    /// All arguments given after `--`.
+    pub fn args() -> Vec<String> {
+        std::env::args().skip(1).collect()
+    }
+
+    /// Whether or not `--name` was given.
+    pub fn flag(name: &str) -> bool {
+        let flag = format!("--{}", name);
+        args().iter().any(|a| *a == flag)
+    }
+
+    /// The value given as `--name value` or `--name=value`, parsed into the requested type.
+    pub fn arg<T: FromStr>(name: &str) -> Option<T> {
+        let flag = format!("--{}", name);
+        let prefix = format!("{}=", flag);
+        let args = args();
+        let value = args.iter().enumerate().find_map(|(i, a)| {
+            if *a == flag {
+                args.get(i + 1).cloned()
+            } else {
+                a.strip_prefix(prefix.as_str()).map(str::to_owned)
+            }
+        })?;
+        Some(parse(&flag, &value))
+    }
+
+    /// The value of an environment variable such as `ZAMM_OUTPUT_DIR`, parsed into the requested
+    /// type.
+    pub fn env<T: FromStr>(name: &str) -> Option<T> {
+        std::env::var(name).ok().map(|value| parse(name, &value))
+    }
+
+    fn parse<T: FromStr>(name: &str, value: &str) -> T {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid value for {}: {}", name, value))
+    }
+}"#;
+
+/// Placeholder in template files for the functions that report generated files back to ZAMM.
+const MANIFEST_PLACEHOLDER: &str = "{{manifest}}";
+
+/// Functions for the codegen binary to report the files it generated with. Files get reported to
+/// the file that `MANIFEST_VAR` points to, once `zamm_manifest::write` is called.
+fn manifest_module() -> String {
+    formatdoc! {r#"
+        /// Files generated by this binary, to be reported back to zamm.
+        #[allow(dead_code)]
+        mod zamm_manifest {{
+            use std::cell::RefCell;
+            use std::path::Path;
+
+            thread_local! {{
+                static FILES: RefCell<Vec<zamm_serde_json::Value>> = RefCell::new(Vec::new());
+            }}
+
+            /// Record a generated file, along with the concept or markdown block responsible for it.
+            pub fn record<P: AsRef<Path>>(path: P, source: &str) {{
+                record_file(path.as_ref(), Some(source));
+            }}
+
+            fn record_file(path: &Path, source: Option<&str>) {{
+                let path = path.to_string_lossy().into_owned();
+                let file = zamm_serde_json::json!({{"path": path, "source": source}});
+                FILES.with(|f| f.borrow_mut().push(file));
+            }}
+
+            /// Record every file that Yang listed as autogenerated. Yang doesn't say which concept
+            /// each file came from, so no source is given for them.
+            pub fn record_autogen_tracker() {{
+                if let Ok(tracker) = std::fs::read_to_string("{tracker}") {{
+                    for line in tracker.lines().filter(|l| !l.is_empty()) {{
+                        record_file(Path::new(line), None);
+                    }}
+                }}
+            }}
+
+            /// Report every recorded file back to zamm.
+            pub fn write() {{
+                let path = match std::env::var("{manifest_var}") {{
+                    Ok(path) => path,
+                    Err(_) => return,
+                }};
+                let files = FILES.with(|f| f.borrow().clone());
+                let report = zamm_serde_json::json!({{"format": {format}, "files": files}});
+                std::fs::write(&path, report.to_string()).unwrap_or_else(|e| {{
+                    panic!("Could not report generated files to {{}}: {{}}", path, e)
+                }});
+            }}
+        }}"#,
+    tracker = AUTOGEN_TRACKER,
+    manifest_var = MANIFEST_VAR,
+    format = MANIFEST_FORMAT}
+}
+
+/// Modules that every built-in template includes.
+fn support_modules() -> String {
+    format!("{}\n\n{}", PARAMS_MODULE, manifest_module())
+}
+
+/// How the literate code gets wrapped into the main function of the codegen binary.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MainTemplate {
+    /// Initialize Yin's knowledge base, and then generate code with Yang using the codegen
+    /// settings.
+    Yang,
+    /// Initialize Yin's knowledge base without generating any code afterwards.
+    Yin,
+    /// Run the literate code as-is.
+    Plain,
+    /// A user-provided template file.
+    File(PathBuf),
+}
+
+impl MainTemplate {
+    /// Look up a built-in template by name. Anything that isn't the name of a built-in template is
+    /// taken to be the path to a template file.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "yang" => Self::Yang,
+            "yin" => Self::Yin,
+            "plain" => Self::Plain,
+            path => Self::File(PathBuf::from(path)),
+        }
+    }
+
+    /// Code to run ahead of the literate code, given the code retrieved from imports. The Yang and
+    /// Yin templates tell Yang which concepts were imported, so that no code gets generated for
+    /// them again.
+    pub fn imported_code(&self, imported: &str) -> String {
+        match self {
+            Self::Yang | Self::Yin if !imported.is_empty() => format!(
+                "zamm_yang::helper::start_imports();\n{}zamm_yang::helper::end_imports();\n",
+                imported
+            ),
+            _ => imported.to_owned(),
+        }
+    }
+
+    /// Generate the code for the codegen binary's main file.
+    pub fn render(&self, main_cfg: &MainConfig) -> Result<String> {
+        self.render_for_yang(main_cfg, None)
+    }
+
+    /// Generate the code for the codegen binary's main file, passing on only the given codegen
+    /// settings to Yang if it's known which ones Yang has.
+    pub fn render_for_yang(
+        &self,
+        main_cfg: &MainConfig,
+        yang_fields: Option<&[String]>,
+    ) -> Result<String> {
+        let imports = main_cfg.imports.iter().format("\n").to_string();
+        let code = main_cfg.lines.iter().format("\n").to_string();
+        match self {
+            Self::Yang => Ok(yang_main(&imports, &code, yang_fields)),
+            Self::Yin => Ok(wrapped_main(
+                &imports,
+                "initialize_kb();",
+                &code,
+                "zamm_manifest::write();",
+            )),
+            Self::Plain => Ok(wrapped_main(&imports, "", &code, "zamm_manifest::write();")),
+            Self::File(path) => {
+                let template = read_to_string(path).map_err(|e| {
+                    Error::new(
+                        e.kind(),
+                        format!("Could not read main template {}: {}", path.display(), e),
+                    )
+                })?;
+                if !template.contains(CODE_PLACEHOLDER) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Main template {} has no {} placeholder",
+                            path.display(),
+                            CODE_PLACEHOLDER
+                        ),
+                    ));
+                }
+                Ok(template
+                    .replace(IMPORTS_PLACEHOLDER, &imports)
+                    .replace(PARAMS_PLACEHOLDER, PARAMS_MODULE)
+                    .replace(MANIFEST_PLACEHOLDER, &manifest_module())
+                    .replace(CODEGEN_CONFIG_VAR_PLACEHOLDER, CODEGEN_CONFIG_VAR)
+                    .replace(CODE_PLACEHOLDER, &code))
+            }
+        }
+    }
+}
+
+/// Generate a main function that runs the literate code between the given prologue and epilogue.
+fn wrapped_main(imports: &str, prologue: &str, code: &str, epilogue: &str) -> String {
+    formatdoc! {r#"
+        {imports}
+
+        {modules}
+
+        fn main() {{
+            {prologue}
+            // ------------------------ START OF LITERATE RUST -------------------------
+        {code}
+            // -------------------------- END OF LITERATE RUST -------------------------
+            {epilogue}
+        }}
+    "#, imports = imports,
+    modules = support_modules(),
+    prologue = prologue,
+    code = code,
+    epilogue = epilogue}
+}
+
+/// Whether Yang has the given codegen setting, assuming that it does if it's not known which ones
+/// it has.
+fn yang_has(yang_fields: Option<&[String]>, setting: &str) -> bool {
+    match yang_fields {
+        Some(fields) => fields.iter().any(|f| f == setting),
+        None => true,
+    }
+}
+
+/// Match arms that copy each codegen setting over to the field of Yang's `CodegenConfig` with the
+/// same name. Settings that Yang doesn't have are skipped, so that the codegen binary still builds
+/// against versions of Yang that are older or newer than this version of zamm. If it's not known
+/// which settings Yang has, all of them are copied.
+fn codegen_setting_arms(yang_fields: Option<&[String]>) -> String {
+    CODEGEN_SETTINGS
+        .iter()
+        .map(|setting| {
+            if yang_has(yang_fields, setting) {
+                format!(
+                    "(\"{setting}\", Some(v)) => codegen_cfg.{setting} = v,",
+                    setting = setting
+                )
+            } else {
+                format!(
+                    "(\"{}\", Some(_)) => (), // not in this version of Yang",
+                    setting
+                )
+            }
+        })
+        .join("\n                ")
+}
+
+/// Generate code for a Yang main function. The codegen settings are not part of the generated
+/// code. Instead, they are read at runtime from the file that `CODEGEN_CONFIG_VAR` points to, so
+/// that changing them doesn't require rebuilding the codegen binary. Any settings that aren't given
+/// keep Yang's defaults.
+///
+/// Yang's `CodegenConfig` can't be deserialized directly, so each setting is copied over by name,
+/// for only those settings that Yang has.
+fn yang_main(imports: &str, code: &str, yang_fields: Option<&[String]>) -> String {
+    formatdoc! {r#"
+        {imports}
+
+        {modules}
+
+        /// Yang's codegen settings, and whether Yang was asked to track the files it generates.
+        fn zamm_codegen_config() -> (CodegenConfig, bool) {{
+            let mut codegen_cfg = CodegenConfig::default();
+            let cfg_path = match std::env::var("{cfg_var}") {{
+                Ok(path) => path,
+                Err(_) => {{
+                    println!("{cfg_var} not set, using default codegen settings.");
+                    return (codegen_cfg, false);
+                }}
+            }};
+            let cfg_json = std::fs::read_to_string(&cfg_path)
+                .unwrap_or_else(|e| panic!("Could not read codegen settings at {{}}: {{}}", cfg_path, e));
+            let cfg: zamm_serde_json::Value = zamm_serde_json::from_str(&cfg_json)
+                .unwrap_or_else(|e| panic!("Codegen settings at {{}} are invalid: {{}}", cfg_path, e));
+            if cfg["format"] != {format} {{
+                println!(
+                    "Codegen settings were written by zamm {{}} in format {{}}, but this binary \
+                    expects format {format}. Some settings may not be applied.",
+                    cfg["zamm_version"], cfg["format"]
+                );
+            }}
+            if let Some(settings) = cfg["codegen"].as_object() {{
+                for (key, value) in settings {{
+                    match (key.as_str(), value.as_bool()) {{
+                        {setting_arms}
+                        _ => println!(
+                            "Ignoring codegen setting {{}} = {{}}, which this version of zamm \
+                            doesn't know how to pass on to Yang.",
+                            key, value
+                        ),
+                    }}
+                }}
+            }}
+            let track_autogen = {track_autogen} && cfg["codegen"]["track_autogen"] == true;
+            (codegen_cfg, track_autogen)
+        }}
+
+        fn main() {{
+            let (codegen_cfg, track_autogen) = zamm_codegen_config();
+
+            initialize_kb();
+            // ------------------------ START OF LITERATE RUST -------------------------
+        {code}
+            // -------------------------- END OF LITERATE RUST -------------------------
+            handle_all_implementations(&codegen_cfg);
+            // Yang only lists the files it generated when tracking them
+            if track_autogen {{
+                zamm_manifest::record_autogen_tracker();
+            }}
+            zamm_manifest::write();
+        }}
+    "#, imports = imports,
+    modules = support_modules(),
+    cfg_var = CODEGEN_CONFIG_VAR,
+    format = CODEGEN_CONFIG_FORMAT,
+    setting_arms = codegen_setting_arms(yang_fields),
+    track_autogen = yang_has(yang_fields, "track_autogen"),
+    code = code}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn main_cfg() -> MainConfig {
+        MainConfig {
+            imports: vec!["use zamm_yang::tao::Tao;".to_owned()],
+            lines: vec!["let x = 1;".to_owned()],
+        }
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(MainTemplate::from_name("yang"), MainTemplate::Yang);
+        assert_eq!(MainTemplate::from_name("plain"), MainTemplate::Plain);
+        assert_eq!(
+            MainTemplate::from_name("templates/main.rs"),
+            MainTemplate::File(PathBuf::from("templates/main.rs"))
+        );
+    }
+
+    #[test]
+    fn test_yang_main_independent_of_codegen_config() {
+        let main_rs = MainTemplate::Yang.render(&main_cfg()).unwrap();
+        assert!(main_rs.starts_with("use zamm_yang::tao::Tao;\n"));
+        assert!(main_rs.contains(CODEGEN_CONFIG_VAR));
+        assert!(main_rs.contains("let x = 1;"));
+        assert!(main_rs.contains("handle_all_implementations(&codegen_cfg);"));
+    }
+
+    #[test]
+    fn test_yang_main_skips_settings_yang_lacks() {
+        let fields = vec!["comment_autogen".to_owned(), "yin".to_owned()];
+        let main_rs = MainTemplate::Yang
+            .render_for_yang(&main_cfg(), Some(&fields))
+            .unwrap();
+        assert!(main_rs.contains("codegen_cfg.comment_autogen = v"));
+        assert!(main_rs.contains("(\"release\", Some(_)) => ()"));
+        assert!(!main_rs.contains("codegen_cfg.release"));
+        assert!(!main_rs.contains("codegen_cfg.track_autogen"));
+
+        let main_rs = MainTemplate::Yang.render(&main_cfg()).unwrap();
+        assert!(main_rs.contains("codegen_cfg.release = v"));
+    }
+
+    #[test]
+    fn test_imported_code() {
+        let imported = "let helper = 1;\n";
+        assert_eq!(
+            MainTemplate::Yang.imported_code(imported),
+            "zamm_yang::helper::start_imports();\nlet helper = 1;\n\
+            zamm_yang::helper::end_imports();\n"
+        );
+        assert_eq!(MainTemplate::Plain.imported_code(imported), imported);
+        assert_eq!(
+            MainTemplate::File(PathBuf::from("main.rs")).imported_code(imported),
+            imported
+        );
+        assert_eq!(MainTemplate::Yin.imported_code(""), "");
+    }
+
+    #[test]
+    fn test_plain_main() {
+        let main_rs = MainTemplate::Plain.render(&main_cfg()).unwrap();
+        assert!(main_rs.starts_with("use zamm_yang::tao::Tao;\n"));
+        assert!(main_rs.contains("let x = 1;"));
+        assert!(!main_rs.contains("initialize_kb"));
+        assert!(!main_rs.contains("handle_all_implementations"));
+        assert!(main_rs.contains("mod zamm_params"));
+        assert!(main_rs.contains("mod zamm_manifest"));
+        assert!(main_rs.contains("zamm_manifest::write();"));
+    }
+
+    #[test]
+    fn test_template_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("template.rs");
+        fs::write(
+            &path,
+            "{{imports}}\nfn main() {\n    setup(\"{{codegen_config_var}}\");\n{{code}}\n}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            MainTemplate::File(path.clone()).render(&main_cfg()).unwrap(),
+            "use zamm_yang::tao::Tao;\nfn main() {\n    setup(\"ZAMM_CODEGEN_CONFIG\");\nlet x = 1;\n}\n"
+        );
+
+        fs::write(&path, "fn main() {}").unwrap();
+        let error = MainTemplate::File(path.clone())
+            .render(&main_cfg())
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::checksum::sha256_hex;
+use crate::project::Project;
+use crate::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// Environment variable that tells the codegen binary where to report the files it generated.
+pub const MANIFEST_VAR: &str = "ZAMM_MANIFEST";
+
+/// File in the build directory that the codegen binary reports its generated files to.
+pub const MANIFEST_REPORT_FILE: &str = "generated.json";
+
+/// File in the build directory that describes every file generated by the last build.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Version of the format that the codegen binary reports generated files in. Bump this whenever
+/// the report changes in a way that older versions of ZAMM can't read.
+pub const MANIFEST_FORMAT: u32 = 1;
+
+/// A file as reported by the codegen binary.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+struct ReportedFile {
+    /// Path to the file, relative to the project root.
+    path: PathBuf,
+    /// The concept or markdown block responsible for the file.
+    source: Option<String>,
+}
+
+/// Everything the codegen binary reports having generated.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+struct Report {
+    /// Version of the report format.
+    format: u32,
+    /// Files generated, in the order they were reported.
+    files: Vec<ReportedFile>,
+}
+
+/// How a generated file compares to the previous build.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    /// The file wasn't generated by the previous build.
+    Created,
+    /// The file was generated by the previous build, but with different contents.
+    Modified,
+    /// The file is exactly what the previous build generated.
+    Unchanged,
+}
+
+impl fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Created => write!(f, "created"),
+            Self::Modified => write!(f, "modified"),
+            Self::Unchanged => write!(f, "unchanged"),
+        }
+    }
+}
+
+/// A file generated by the codegen binary.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GeneratedFile {
+    /// Path to the file, relative to the project root.
+    pub path: PathBuf,
+    /// SHA-256 checksum of the file's contents.
+    pub sha256: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// The concept or markdown block responsible for the file, if the codegen binary said.
+    pub source: Option<String>,
+    /// How the file compares to the previous build.
+    pub status: FileStatus,
+}
+
+/// The same file reported as coming from more than one source.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Conflict {
+    /// Path to the file, relative to the project root.
+    pub path: PathBuf,
+    /// Every source that reported generating the file.
+    pub sources: Vec<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} was generated by more than one source: {}",
+            self.path.display(),
+            self.sources.join(", ")
+        )
+    }
+}
+
+/// Description of every file generated by a build.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The generated files, sorted by path.
+    pub files: Vec<GeneratedFile>,
+}
+
+fn invalid_report(path: &Path, error: impl fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "Codegen binary reported invalid output at {}: {}",
+            path.display(),
+            error
+        ),
+    )
+}
+
+impl Manifest {
+    /// Where the manifest of the given project is kept.
+    pub fn path(project: &Project) -> PathBuf {
+        project.build_dir().join(MANIFEST_FILE)
+    }
+
+    /// Load the manifest of the project's last build, if there is one.
+    pub fn load(project: &Project) -> Result<Option<Self>> {
+        let path = Self::path(project);
+        if !path.exists() {
+            return Ok(None);
+        }
+        serde_json::from_str(&fs::read_to_string(&path)?)
+            .map(Some)
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid manifest at {}: {}", path.display(), e),
+                )
+            })
+    }
+
+    /// Save this as the manifest of the project's last build.
+    pub fn save(&self, project: &Project) -> Result<()> {
+        fs::create_dir_all(project.build_dir())?;
+        fs::write(Self::path(project), serde_json::to_string_pretty(self)?)
+    }
+
+    /// Look up a generated file by its path relative to the project root.
+    pub fn get(&self, path: &Path) -> Option<&GeneratedFile> {
+        self.files.iter().find(|f| f.path == path)
+    }
+
+    /// Build a manifest out of the files that the codegen binary reported at `report_path`, by
+    /// looking at what they contain now. Files are compared against the previous build's manifest
+    /// if there is one. Also returns every file that was reported by more than one source.
+    pub fn from_report(
+        project: &Project,
+        report_path: &Path,
+        previous: Option<&Manifest>,
+    ) -> Result<(Self, Vec<Conflict>)> {
+        let report: Report = serde_json::from_str(&fs::read_to_string(report_path)?)
+            .map_err(|e| invalid_report(report_path, e))?;
+        if report.format != MANIFEST_FORMAT {
+            return Err(invalid_report(
+                report_path,
+                format!(
+                    "expected format {}, got format {}",
+                    MANIFEST_FORMAT, report.format
+                ),
+            ));
+        }
+
+        let mut sources: BTreeMap<PathBuf, Vec<Option<String>>> = BTreeMap::new();
+        for file in report.files {
+            let file_sources = sources.entry(file.path).or_default();
+            if !file_sources.contains(&file.source) {
+                file_sources.push(file.source);
+            }
+        }
+
+        let mut manifest = Self::default();
+        let mut conflicts = vec![];
+        for (path, file_sources) in sources {
+            let contents = fs::read(project.path(&path))
+                .map_err(|e| invalid_report(report_path, format!("{}: {}", path.display(), e)))?;
+            let sha256 = sha256_hex(&contents);
+            let status = match previous.and_then(|p| p.get(&path)) {
+                None => FileStatus::Created,
+                Some(before) if before.sha256 == sha256 => FileStatus::Unchanged,
+                Some(_) => FileStatus::Modified,
+            };
+            let named_sources: Vec<String> = file_sources.iter().flatten().cloned().collect();
+            if named_sources.len() > 1 {
+                conflicts.push(Conflict {
+                    path: path.clone(),
+                    sources: named_sources.clone(),
+                });
+            }
+            manifest.files.push(GeneratedFile {
+                path,
+                sha256,
+                size: contents.len() as u64,
+                source: named_sources.into_iter().next(),
+                status,
+            });
+        }
+        Ok((manifest, conflicts))
+    }
+
+    /// Number of generated files with the given status.
+    pub fn count(&self, status: FileStatus) -> usize {
+        self.files.iter().filter(|f| f.status == status).count()
+    }
+
+    /// Human-readable summary of what got generated.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Generated {} files: {} created, {} modified, {} unchanged",
+            self.files.len(),
+            self.count(FileStatus::Created),
+            self.count(FileStatus::Modified),
+            self.count(FileStatus::Unchanged)
+        );
+        for file in self
+            .files
+            .iter()
+            .filter(|f| f.status != FileStatus::Unchanged)
+        {
+            summary += &format!("\n  {} {}", file.status, file.path.display());
+            if let Some(source) = &file.source {
+                summary += &format!(" (from {})", source);
+            }
+        }
+        summary
+    }
+}
+
+/// Read what the codegen binary reported generating, print a summary of it, and save it as the
+/// project's manifest. Nothing happens if the binary didn't report anything.
+pub fn record_manifest(project: &Project, report_path: &Path) -> Result<()> {
+    if !report_path.exists() {
+        return Ok(());
+    }
+    let previous = Manifest::load(project).unwrap_or(None);
+    let (manifest, conflicts) = Manifest::from_report(project, report_path, previous.as_ref())?;
+    for conflict in conflicts {
+        warn!("{}", conflict);
+    }
+    println!("{}", manifest.summary());
+    manifest.save(project)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn temp_project() -> (TempDir, Project) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        let project = Project::new(dir.path()).unwrap();
+        (dir, project)
+    }
+
+    fn report(project: &Project, files: &[(&str, Option<&str>)]) -> PathBuf {
+        let files: Vec<serde_json::Value> = files
+            .iter()
+            .map(|(path, source)| serde_json::json!({"path": path, "source": source}))
+            .collect();
+        let path = project.build_dir().join(MANIFEST_REPORT_FILE);
+        fs::create_dir_all(project.build_dir()).unwrap();
+        fs::write(
+            &path,
+            serde_json::json!({"format": MANIFEST_FORMAT, "files": files}).to_string(),
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_manifest_from_report() {
+        let (_dir, project) = temp_project();
+        fs::write(project.path("src/a.rs"), "fn a() {}").unwrap();
+        fs::write(project.path("src/b.rs"), "fn b() {}").unwrap();
+        let report_path = report(
+            &project,
+            &[("src/b.rs", Some("Tao")), ("src/a.rs", Some("Attribute"))],
+        );
+        let (first, conflicts) = Manifest::from_report(&project, &report_path, None).unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(first.count(FileStatus::Created), 2);
+        assert_eq!(first.files[0].path, PathBuf::from("src/a.rs"));
+        assert_eq!(first.files[0].size, 9);
+        assert_eq!(first.files[0].sha256, sha256_hex(b"fn a() {}"));
+        assert_eq!(first.files[0].source, Some("Attribute".to_owned()));
+
+        fs::write(project.path("src/b.rs"), "fn b() { 1; }").unwrap();
+        let (second, _) = Manifest::from_report(&project, &report_path, Some(&first)).unwrap();
+        assert_eq!(
+            second.get(Path::new("src/a.rs")).unwrap().status,
+            FileStatus::Unchanged
+        );
+        assert_eq!(
+            second.get(Path::new("src/b.rs")).unwrap().status,
+            FileStatus::Modified
+        );
+
+        second.save(&project).unwrap();
+        assert_eq!(Manifest::load(&project).unwrap(), Some(second));
+    }
+
+    #[test]
+    fn test_conflicting_sources() {
+        let (_dir, project) = temp_project();
+        fs::write(project.path("src/a.rs"), "").unwrap();
+        let report_path = report(
+            &project,
+            &[
+                ("src/a.rs", Some("Tao")),
+                ("src/a.rs", Some("Tao")),
+                ("src/a.rs", Some("Form")),
+            ],
+        );
+        let (_, conflicts) = Manifest::from_report(&project, &report_path, None).unwrap();
+        assert_eq!(
+            conflicts,
+            vec![Conflict {
+                path: PathBuf::from("src/a.rs"),
+                sources: vec!["Tao".to_owned(), "Form".to_owned()],
+            }]
+        );
+    }
+}
@@ -1,7 +1,36 @@
+/// Code generators that turn parsed input files into outputs.
+mod backend;
+/// Cache of previously built codegen binaries.
+mod binary_cache;
+/// Options for building the codegen binary.
+mod build_config;
 /// Generate code files using Rust code that effectively serves as a `build.rs`.
 mod build_logic;
+/// Seeing what a build would change without touching the project.
+mod dry_run;
+/// Keeping the dependency versions of the intermediate build around.
+mod lock_file;
+/// Wrapping the literate code in a main function.
+mod main_template;
+/// Records of the files that the codegen binary generated.
+mod manifest;
+/// Separating imports from the rest of the literate code.
+mod rust_imports;
+/// Finding out which codegen settings the version of Yang being built against has.
+mod yang_settings;
 /// Structs, mostly copied from Yang.
 mod yang_structs;
 
-pub use build_logic::generate_final_code;
-pub use yang_structs::{CodegenConfig, MainConfig};
+pub use backend::{Backend, CargoBackend, CheckBackend, DryRunBackend, TangleBackend};
+pub use build_config::{BuildConfig, ZAMM_TARGET_DIR_VAR};
+pub use build_logic::{
+    clean_shared_target, dry_run_final_code, generate_final_code, intermediate_sources,
+    IntermediateSources,
+};
+pub use dry_run::FileChange;
+pub use lock_file::INTERMEDIATE_LOCK_FILE;
+pub use main_template::{MainTemplate, DEFAULT_TEMPLATE};
+pub use manifest::{
+    record_manifest, Conflict, FileStatus, GeneratedFile, Manifest, MANIFEST_FILE, MANIFEST_VAR,
+};
+pub use yang_structs::{CodegenConfig, MainConfig, CODEGEN_SETTINGS};
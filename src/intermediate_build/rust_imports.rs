@@ -0,0 +1,253 @@
+use super::MainConfig;
+use crate::warn;
+use itertools::Itertools;
+use proc_macro2::LineColumn;
+use quote::ToTokens;
+use std::collections::BTreeSet;
+use std::ops::Range;
+use syn::spanned::Spanned;
+use syn::{Attribute, Block, Item, ItemUse, Stmt, UseTree, Visibility};
+
+/// Render attributes and visibility the way they would prefix an item.
+fn item_prefix(attrs: &[Attribute], vis: &Visibility) -> String {
+    let mut prefix = String::new();
+    for attr in attrs {
+        prefix += &attr.to_token_stream().to_string();
+        prefix.push(' ');
+    }
+    let vis = vis.to_token_stream().to_string();
+    if !vis.is_empty() {
+        prefix += &vis;
+        prefix.push(' ');
+    }
+    prefix
+}
+
+/// Flatten a use tree into the individual paths it imports, so that `use a::{b, c};` becomes
+/// `a::b` and `a::c`.
+fn flatten_use_tree(tree: &UseTree, parent: &str, paths: &mut Vec<String>) {
+    let join = |segment: &str| {
+        if parent.is_empty() || parent == "::" {
+            format!("{}{}", parent, segment)
+        } else {
+            format!("{}::{}", parent, segment)
+        }
+    };
+    match tree {
+        UseTree::Path(path) => flatten_use_tree(&path.tree, &join(&path.ident.to_string()), paths),
+        UseTree::Name(name) if name.ident == "self" => paths.push(parent.to_owned()),
+        UseTree::Name(name) => paths.push(join(&name.ident.to_string())),
+        UseTree::Rename(rename) if rename.ident == "self" => {
+            paths.push(format!("{} as {}", parent, rename.rename))
+        }
+        UseTree::Rename(rename) => paths.push(format!(
+            "{} as {}",
+            join(&rename.ident.to_string()),
+            rename.rename
+        )),
+        UseTree::Glob(_) => paths.push(join("*")),
+        UseTree::Group(group) => {
+            for item in &group.items {
+                flatten_use_tree(item, parent, paths);
+            }
+        }
+    }
+}
+
+/// Turn a use declaration into one single-path use declaration per imported path.
+fn flatten_use(item: &ItemUse) -> Vec<String> {
+    let mut paths = vec![];
+    let root = if item.leading_colon.is_some() {
+        "::"
+    } else {
+        ""
+    };
+    flatten_use_tree(&item.tree, root, &mut paths);
+    let prefix = item_prefix(&item.attrs, &item.vis);
+    paths
+        .into_iter()
+        .map(|path| format!("{}use {};", prefix, path))
+        .collect()
+}
+
+/// Byte offsets of the start of each line in the text.
+fn line_offsets(text: &str) -> Vec<usize> {
+    let mut offsets = vec![0];
+    offsets.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+    offsets
+}
+
+/// Byte offset of a location reported by `proc_macro2`, whose lines are 1-indexed and whose
+/// columns are counted in characters.
+fn byte_offset(text: &str, lines: &[usize], location: LineColumn) -> usize {
+    let line_start = lines[location.line - 1];
+    text[line_start..]
+        .char_indices()
+        .nth(location.column)
+        .map(|(i, _)| line_start + i)
+        .unwrap_or_else(|| text.len())
+}
+
+/// Whether or not this statement has to go outside of the main function.
+fn is_hoisted(stmt: &Stmt) -> bool {
+    matches!(
+        stmt,
+        Stmt::Item(Item::Use(_)) | Stmt::Item(Item::ExternCrate(_))
+    )
+}
+
+/// Separate imports embedded in the code, similar to how `rustdoc` does it. Top-level `use`
+/// declarations (including multi-line and `pub` ones) and `extern crate` items, along with their
+/// attributes, get moved out of the main function. Imports of the same path are only kept once,
+/// even when they come from different `use` declarations. Anything nested inside other code stays
+/// where it is.
+///
+/// Returns `None` if the code can't be parsed as Rust.
+pub fn parse_imports(code: &str) -> Option<MainConfig> {
+    // keep the code on its own lines, so that line numbers are only off by one
+    let wrapped = format!("{{\n{}\n}}", code);
+    let block = syn::parse_str::<Block>(&wrapped).ok()?;
+    let lines = line_offsets(&wrapped);
+
+    let mut extern_crates = BTreeSet::new();
+    let mut uses = BTreeSet::new();
+    let mut seen_statements = BTreeSet::new();
+    let mut hoisted_ranges: Vec<Range<usize>> = vec![];
+    for stmt in block.stmts.iter().filter(|s| is_hoisted(s)) {
+        let span = stmt.span();
+        let range =
+            byte_offset(&wrapped, &lines, span.start())..byte_offset(&wrapped, &lines, span.end());
+        let statement = wrapped[range.clone()].trim().to_owned();
+        if !seen_statements.insert(statement.clone()) {
+            warn!("Repeated import found: {}", statement);
+        }
+        match stmt {
+            Stmt::Item(Item::Use(item)) => uses.extend(flatten_use(item)),
+            _ => {
+                extern_crates.insert(statement);
+            }
+        }
+        hoisted_ranges.push(range);
+    }
+
+    let mut remaining = String::new();
+    let mut position = 0;
+    for range in hoisted_ranges {
+        remaining += &wrapped[position..range.start];
+        position = range.end;
+    }
+    remaining += &wrapped[position..];
+    // strip the braces that were added for parsing
+    let remaining = &remaining[2..remaining.len() - 2];
+
+    let lines: Vec<&str> = remaining
+        .split('\n')
+        .filter(|l| !l.trim().is_empty())
+        .collect();
+    let mut combined_lines = vec![];
+    if !lines.is_empty() {
+        // combine lines together into one fragment to preserve indentation
+        combined_lines.push(lines.iter().format("\n").to_string());
+    }
+    Some(MainConfig {
+        imports: extern_crates.into_iter().chain(uses).collect(),
+        lines: combined_lines,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    fn imports_of(code: &str) -> Vec<String> {
+        parse_imports(code).unwrap().imports
+    }
+
+    #[test]
+    fn test_multiline_use() {
+        assert_eq!(
+            parse_imports(indoc! {"
+                use std::collections::{
+                    HashMap,
+                    HashSet,
+                };
+                let x = 1;"}),
+            Some(MainConfig {
+                imports: vec![
+                    "use std::collections::HashMap;".to_owned(),
+                    "use std::collections::HashSet;".to_owned(),
+                ],
+                lines: vec!["let x = 1;".to_owned()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_semantic_duplicates_merged() {
+        assert_eq!(
+            imports_of(indoc! {"
+                use a::b;
+                use a::{b, c};
+                use a::{self, c as d};"}),
+            vec!["use a::b;", "use a::c as d;", "use a::c;", "use a;"]
+        );
+    }
+
+    #[test]
+    fn test_pub_and_indented_use() {
+        assert_eq!(
+            imports_of("pub use a::b;\n    use c::d;\nuse ::e::*;"),
+            vec!["pub use a::b;", "use ::e::*;", "use c::d;"]
+        );
+    }
+
+    #[test]
+    fn test_extern_crate() {
+        let main_cfg = parse_imports(indoc! {"
+            #[macro_use]
+            extern crate lazy_static;
+            use lazy_static::lazy_static;
+            let x = 1;"})
+        .unwrap();
+        assert_eq!(
+            main_cfg.imports,
+            vec![
+                "#[macro_use]\nextern crate lazy_static;",
+                "use lazy_static::lazy_static;"
+            ]
+        );
+        assert_eq!(main_cfg.lines, vec!["let x = 1;"]);
+    }
+
+    #[test]
+    fn test_nested_use_stays() {
+        let code = indoc! {"
+            fn helper() {
+            use std::rc::Rc;
+            }
+            let x = 1;"};
+        assert_eq!(
+            parse_imports(code),
+            Some(MainConfig {
+                imports: vec![],
+                lines: vec![code.to_owned()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_comments_kept() {
+        assert_eq!(
+            parse_imports("// say hi\nprintln!(\"hi\"); use a::b;\nlet y = 2;")
+                .unwrap()
+                .lines,
+            vec!["// say hi\nprintln!(\"hi\"); \nlet y = 2;"]
+        );
+    }
+
+    #[test]
+    fn test_unparseable() {
+        assert_eq!(parse_imports("let x = ;"), None);
+    }
+}
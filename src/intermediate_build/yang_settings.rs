@@ -0,0 +1,163 @@
+use super::{BuildConfig, CODEGEN_SETTINGS};
+use crate::commands::run_command_streaming_stderr;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use syn::{Fields, Item};
+
+/// Name of Yang's package.
+const YANG_PACKAGE: &str = "zamm_yang";
+
+/// Name of the struct that Yang takes its codegen settings in.
+const CODEGEN_STRUCT: &str = "CodegenConfig";
+
+/// Names of the fields of the struct with the given name, if it's defined among the given items or
+/// inside any of their inline modules.
+fn struct_fields(items: &[Item], name: &str) -> Option<Vec<String>> {
+    items.iter().find_map(|item| match item {
+        Item::Struct(s) if s.ident == name => match &s.fields {
+            Fields::Named(fields) => Some(
+                fields
+                    .named
+                    .iter()
+                    .filter_map(|f| f.ident.as_ref().map(|i| i.to_string()))
+                    .collect(),
+            ),
+            _ => None,
+        },
+        Item::Mod(m) => m
+            .content
+            .as_ref()
+            .and_then(|(_, items)| struct_fields(items, name)),
+        _ => None,
+    })
+}
+
+/// Every Rust file in the given directory and its subdirectories.
+fn rust_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.is_dir() {
+            rust_files(&path, files);
+        } else if path.extension() == Some(OsStr::new("rs")) {
+            files.push(path);
+        }
+    }
+}
+
+/// Directory of the given package, among the packages that Cargo resolved.
+fn package_dir(metadata: &serde_json::Value, package: &str) -> Option<PathBuf> {
+    let manifest = metadata["packages"]
+        .as_array()?
+        .iter()
+        .find(|p| p["name"] == package)?["manifest_path"]
+        .as_str()?;
+    Path::new(manifest).parent().map(Path::to_path_buf)
+}
+
+/// Fields of the codegen settings struct in the given package's sources.
+fn codegen_fields(package_dir: &Path) -> Option<Vec<String>> {
+    let mut files = vec![];
+    rust_files(&package_dir.join("src"), &mut files);
+    files.sort();
+    files.iter().find_map(|file| {
+        let source = fs::read_to_string(file).ok()?;
+        struct_fields(&syn::parse_file(&source).ok()?.items, CODEGEN_STRUCT)
+    })
+}
+
+/// The codegen settings that the version of Yang that the build directory's crate depends on
+/// supports, found by looking through Yang's sources where Cargo put them. Cargo is held to the
+/// build directory's lock file if `locked` is set. Returns `None` if Yang's settings couldn't be
+/// found, in which case the build itself will report whatever went wrong.
+pub fn yang_codegen_fields(
+    build_dir: &Path,
+    build_cfg: &BuildConfig,
+    locked: bool,
+) -> Option<Vec<String>> {
+    // Cargo won't read a manifest without any targets
+    let main_rs = build_dir.join("src/main.rs");
+    if !main_rs.exists() {
+        fs::create_dir_all(main_rs.parent()?).ok()?;
+        fs::write(&main_rs, "fn main() {}\n").ok()?;
+    }
+    let mut args = build_cfg.toolchain_args();
+    args.extend(vec![
+        "metadata".to_owned(),
+        "--format-version".to_owned(),
+        "1".to_owned(),
+    ]);
+    if build_cfg.offline {
+        args.push("--offline".to_owned());
+    }
+    if build_cfg.frozen {
+        args.push("--frozen".to_owned());
+    } else if locked {
+        args.push("--locked".to_owned());
+    }
+    let output = run_command_streaming_stderr("cargo", &args, &[], build_dir).ok()?;
+    let metadata = serde_json::from_str(&output).ok()?;
+    codegen_fields(&package_dir(&metadata, YANG_PACKAGE)?)
+}
+
+/// Warnings about the codegen settings that zamm and Yang don't have in common, which keep their
+/// defaults.
+pub fn skew_warnings(yang_fields: &[String]) -> Vec<String> {
+    let missing = CODEGEN_SETTINGS
+        .iter()
+        .filter(|s| !yang_fields.iter().any(|f| f == *s))
+        .map(|s| {
+            format!(
+                "This version of Yang has no codegen setting {}, so it will be left out.",
+                s
+            )
+        });
+    let unknown = yang_fields
+        .iter()
+        .filter(|f| !CODEGEN_SETTINGS.contains(&f.as_str()))
+        .map(|f| {
+            format!(
+                "Yang has a codegen setting {} that this version of zamm doesn't know about, so \
+                Yang's default will be used for it.",
+                f
+            )
+        });
+    missing.chain(unknown).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_struct_fields() {
+        let file = syn::parse_file(
+            "pub mod codegen {
+                pub struct CodegenConfig { pub yin: bool, pub release: bool }
+            }
+            pub struct Other { pub x: u32 }",
+        )
+        .unwrap();
+        assert_eq!(
+            struct_fields(&file.items, "CodegenConfig"),
+            Some(vec!["yin".to_owned(), "release".to_owned()])
+        );
+        assert_eq!(struct_fields(&file.items, "Missing"), None);
+    }
+
+    #[test]
+    fn test_skew_warnings() {
+        let mut fields: Vec<String> = CODEGEN_SETTINGS.iter().map(|s| (*s).to_owned()).collect();
+        assert!(skew_warnings(&fields).is_empty());
+
+        fields.retain(|f| f != "release");
+        fields.push("parallel".to_owned());
+        let warnings = skew_warnings(&fields);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("no codegen setting release"));
+        assert!(warnings[1].contains("setting parallel that this version of zamm"));
+    }
+}
@@ -1,3 +1,5 @@
+use serde::Serialize;
+
 /// Output config for main function code generation.
 #[derive(Eq, PartialEq, Debug)]
 pub struct MainConfig {
@@ -8,8 +10,18 @@ pub struct MainConfig {
     pub lines: Vec<String>,
 }
 
+/// Names of the codegen settings that zamm knows how to pass on to Yang, which are also the names
+/// of the fields of `CodegenConfig`.
+pub const CODEGEN_SETTINGS: &[&str] = &[
+    "comment_autogen",
+    "add_rustfmt_attributes",
+    "track_autogen",
+    "yin",
+    "release",
+];
+
 /// Runtime options for code generation. Copied from Yang.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Serialize)]
 pub struct CodegenConfig {
     /// Whether or not to mark each generated line of code with the autogeneration comment
     /// specified by `zamm_yang::codegen::mark_autogen::AUTOGENERATION_MARKER`.
@@ -43,3 +55,17 @@ impl Default for CodegenConfig {
         }
     }
 }
+
+impl CodegenConfig {
+    /// Settings for generating code for a release. `zamm check --release` uses the same ones, so
+    /// that a release tree is compared against what a release build would generate.
+    pub fn release(yin: bool) -> Self {
+        Self {
+            comment_autogen: false,
+            add_rustfmt_attributes: true,
+            track_autogen: false,
+            yin,
+            release: true,
+        }
+    }
+}
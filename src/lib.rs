@@ -20,55 +20,135 @@
 #![warn(missing_docs)]
 #![allow(clippy::needless_doctest_main)]
 
+/// Talking to Cargo when ZAMM runs from a build script.
+pub mod build_script;
+/// Content checksums.
+mod checksum;
 /// Running commandline commands.
 pub mod commands;
+/// Project configuration.
+pub mod config;
 /// Creating the intermediate build binary.
 pub mod intermediate_build;
 /// Finding and parsing the input files.
 pub mod parse;
+/// The project that ZAMM runs on.
+pub mod project;
+/// Measuring how long each part of a build takes.
+pub mod timings;
+/// Cargo workspaces with several literate crates.
+pub mod workspace;
 
-use intermediate_build::generate_final_code;
-pub use intermediate_build::CodegenConfig;
-use parse::{find_file, parse_input, ParseOutput};
+use intermediate_build::CargoBackend;
+pub use intermediate_build::{Backend, BuildConfig, CodegenConfig};
+use parse::{find_file, parse_input, ParseConfig, ParseOutput};
+pub use project::Project;
 use std::io::Error;
+pub use workspace::Workspace;
 
-/// Generates an intermediate binary from the given file and runs it. If no file is specified, then
-/// it will search for a `yin.md` file in the current directory.
+/// Generates an intermediate binary from the given file and runs it for the project in the current
+/// working directory. If no file is specified, then the input file set in the project
+/// configuration is used, if there is one. Otherwise, it will search for a `yin.md` file in the
+/// project directory.
 ///
-/// Returns the contents of the input file.
+/// Returns the contents of the input file. If an import could not be retrieved, the returned error
+/// will wrap a `parse::ImportError` that can be retrieved with `ImportError::from_io_error`.
 pub fn generate_code(
     input_file: Option<&str>,
     codegen_cfg: &CodegenConfig,
+) -> Result<ParseOutput, Error> {
+    generate_project_code(&Project::current()?, input_file, codegen_cfg)
+}
+
+/// Same as `generate_code`, except for the given project instead of the one in the current working
+/// directory. The working directory of the process is never changed, so several projects can be
+/// built at once.
+pub fn generate_project_code(
+    project: &Project,
+    input_file: Option<&str>,
+    codegen_cfg: &CodegenConfig,
+) -> Result<ParseOutput, Error> {
+    let zamm_cfg = project.config()?;
+    generate_code_with_config(
+        project,
+        input_file.or(zamm_cfg.input.as_deref()),
+        codegen_cfg,
+        &project.parse_config(&zamm_cfg)?,
+        &zamm_cfg.build,
+    )
+}
+
+/// Same as `generate_project_code`, except with explicit settings for how the input file gets
+/// parsed and how the intermediate binary gets built. A main template set in the input file's
+/// front matter takes precedence over the one in `build_cfg`.
+pub fn generate_code_with_config(
+    project: &Project,
+    input_file: Option<&str>,
+    codegen_cfg: &CodegenConfig,
+    parse_cfg: &ParseConfig,
+    build_cfg: &BuildConfig,
+) -> Result<ParseOutput, Error> {
+    generate_code_with_backend(
+        project,
+        input_file,
+        codegen_cfg,
+        parse_cfg,
+        &CargoBackend {
+            build_cfg: build_cfg.clone(),
+        },
+    )
+}
+
+/// Same as `generate_code_with_config`, except that the parsed input file is handed to the given
+/// backend instead of being built into a Cargo binary.
+pub fn generate_code_with_backend(
+    project: &Project,
+    input_file: Option<&str>,
+    codegen_cfg: &CodegenConfig,
+    parse_cfg: &ParseConfig,
+    backend: &dyn Backend,
 ) -> Result<ParseOutput, Error> {
     // no need to regenerate autogenerated files every time
-    println!("cargo:rerun-if-changed=build.rs");
-    let found_input = find_file(input_file)?;
-    let literate_rust_code = parse_input(found_input)?;
-    generate_final_code(&literate_rust_code.extractions, codegen_cfg).map(|_| literate_rust_code)
+    build_script::rerun_if_changed("build.rs");
+    let found_input = find_file(&project.root, input_file)?;
+    let literate_rust_code = parse_input(found_input, parse_cfg)?;
+    backend
+        .generate(project, &literate_rust_code, codegen_cfg)
+        .map(|_| literate_rust_code)
 }
 
-/// Generates an intermediate binary from the given file and runs it with default codegen settings.
-/// Recommended for automatic Cargo builds.
+/// Generates an intermediate binary from the given file and runs it with the codegen settings from
+/// the project configuration. Recommended for automatic Cargo builds, and always runs in build
+/// script mode.
 pub fn generate_default_code(input_file: &str) -> Result<ParseOutput, Error> {
-    generate_code(Some(input_file), &CodegenConfig::default())
+    build_script::enable();
+    let project = Project::current()?;
+    generate_project_code(
+        &project,
+        Some(input_file),
+        &project.config()?.codegen_config(),
+    )
 }
 
-/// Generates code specifically for Yin.
+/// Generates code specifically for Yin, in build script mode.
 pub fn generate_yin_code(input_file: &str) -> Result<ParseOutput, Error> {
-    generate_code(
+    build_script::enable();
+    let project = Project::current()?;
+    generate_project_code(
+        &project,
         Some(input_file),
         &CodegenConfig {
             yin: true,
-            ..CodegenConfig::default()
+            ..project.config()?.codegen_config()
         },
     )
 }
 
-/// Prints a warning to stdout.
+/// Prints a warning to stderr, or hands it to Cargo in build script mode.
 #[macro_export]
 macro_rules! warn {
-    ($warning:tt) => (println!("{}", colored::Colorize::bold(colored::Colorize::yellow($warning))));
+    ($warning:tt) => ($crate::build_script::print_warning($warning));
     ($($arg:tt)*) => ({
-        println!("{}", colored::Colorize::bold(colored::Colorize::yellow(format!($($arg)*).as_str())));
+        $crate::build_script::print_warning(format!($($arg)*));
     })
 }
@@ -5,28 +5,30 @@ use std::env;
 use std::fs;
 use std::fs::read_to_string;
 use std::io::{Error, ErrorKind, Result};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
 use std::process::exit;
 use toml::Value;
 use zamm::commands::run_command;
-use zamm::generate_code;
+use zamm::commands::GraphFormat;
+use zamm::config::ZammConfig;
 use zamm::intermediate_build::CodegenConfig;
-use zamm::parse::ParseOutput;
+use zamm::intermediate_build::{CheckBackend, DryRunBackend};
+use zamm::parse::{
+    explain_overrides, find_file, locate_file, resolve_import_locations, ParseConfig, ParseOutput,
+};
+use zamm::timings::{self, Timings};
 use zamm::{commands, warn};
+use zamm::{
+    generate_code_with_backend, generate_code_with_config, BuildConfig, Project, Workspace,
+};
 
 /// Help text to display for the input file argument.
 const INPUT_HELP_TEXT: &str =
     "The input file containing relevant information to generate code for. Currently only Markdown \
-    (extension .md) is supported. If no input file is provided, yang will look for a file named \
-    `yin` with one of the above extensions, in the current directory.";
-
-/// GCS bucket containing all build files.
-const GCS_BUCKET: &str = "api.zamm.dev";
-
-/// Long-running release branch name.
-const RELEASE_BRANCH: &str = "releases";
-
-/// Short-lived temp branch for commit munging.
-const TEMP_BRANCH: &str = "zamm-temp-release";
+    (extension .md) is supported. If no input file is provided, the one set in zamm.toml is used. \
+    Failing that, yang will look for a file named `yin` with one of the above extensions, in the \
+    current directory.";
 
 /// Filename for project Cargo file.
 const CARGO_FILE: &str = "Cargo.toml";
@@ -41,7 +43,7 @@ struct ProjectInfo {
 }
 
 /// Prepare for release build.
-fn release_pre_build() -> Result<()> {
+fn release_pre_build(project: &Project) -> Result<()> {
     if !run_command("git", &["status", "--porcelain"])?.is_empty() {
         return Err(Error::new(
             ErrorKind::InvalidData,
@@ -53,7 +55,7 @@ fn release_pre_build() -> Result<()> {
             ),
         ));
     }
-    commands::clean()?;
+    commands::clean(project)?;
     Ok(())
 }
 
@@ -107,7 +109,9 @@ fn next_version_string(current_version: &str) -> String {
 }
 
 /// Destructively prepare repo for release after build.
-fn release_post_build(output: &ParseOutput) -> Result<()> {
+fn release_post_build(output: &ParseOutput, zamm_cfg: &ZammConfig) -> Result<()> {
+    let branches = &zamm_cfg.release;
+    let storage = &zamm_cfg.storage;
     let mut project = load_project_info()?;
     if project.version.contains('-') {
         // get rid of non-prod tag (e.g. "0.0.1-beta" becomes "0.0.1")
@@ -119,11 +123,11 @@ fn release_post_build(output: &ParseOutput) -> Result<()> {
     let build_commit = get_commit_sha("HEAD")?;
 
     // Git commands:
-    if branch_exists(TEMP_BRANCH) {
+    if branch_exists(&branches.temp_branch) {
         // force remove temp branch, as it won't be useful for anything else
-        run_command("git", &["branch", "-D", TEMP_BRANCH])?;
+        run_command("git", &["branch", "-D", &branches.temp_branch])?;
     }
-    run_command("git", &["checkout", "-b", TEMP_BRANCH])?;
+    run_command("git", &["checkout", "-b", &branches.temp_branch])?;
     // remove build.rs because it won't be useful on docs.rs anyways
     run_command("git", &["rm", "-f", "build.rs"])?;
     // reformat code
@@ -132,37 +136,50 @@ fn release_post_build(output: &ParseOutput) -> Result<()> {
     let commit_message = format!("Creating release v{}", project.version);
     commit_all(&commit_message)?;
 
-    if branch_exists(RELEASE_BRANCH) {
+    if branch_exists(&branches.branch) {
         // release branch already exists, diff with the last commit
-        run_command("git", &["checkout", RELEASE_BRANCH])?;
-        run_command("git", &["merge", "-s", "ours", "main", "-m", "Dummy merge"])?;
-        run_command("git", &["checkout", TEMP_BRANCH])?;
-        let release_head = format!("refs/heads/{}", RELEASE_BRANCH);
+        run_command("git", &["checkout", &branches.branch])?;
+        run_command(
+            "git",
+            &[
+                "merge",
+                "-s",
+                "ours",
+                &branches.main_branch,
+                "-m",
+                "Dummy merge",
+            ],
+        )?;
+        run_command("git", &["checkout", &branches.temp_branch])?;
+        let release_head = format!("refs/heads/{}", branches.branch);
         run_command("git", &["symbolic-ref", "HEAD", &release_head])?;
-        run_command("git", &["commit", "-a", "--amend", "-C", TEMP_BRANCH])?;
+        run_command(
+            "git",
+            &["commit", "-a", "--amend", "-C", &branches.temp_branch],
+        )?;
     } else {
         // release branch doesn't yet exist, creating it is all we need to do
-        run_command("git", &["checkout", "-b", RELEASE_BRANCH])?;
+        run_command("git", &["checkout", "-b", &branches.branch])?;
     }
     let version_tag = format!("v{}", project.version);
     run_command("git", &["tag", &version_tag])?;
     // Temp branch cleanup
-    run_command("git", &["branch", "-D", TEMP_BRANCH])?;
+    run_command("git", &["branch", "-D", &branches.temp_branch])?;
 
     // Upload build file to GCS
     match env::var("SERVICE_ACCOUNT") {
         Ok(_) => {
             // remove zamm_ prefix for official ZAMM projects
             let canonical_name = project.name.replace("zamm_", "");
-            let gcs_path = format!("v1/books/zamm/{}/{}/{}", canonical_name, project.version, output.filename);
-            let url = format!("https://api.zamm.dev/{}", gcs_path);
+            let gcs_path = format!("{}/{}/{}/{}", storage.prefix, canonical_name, project.version, output.filename);
+            let url = format!("https://{}/{}", storage.bucket, gcs_path);
             // we just want to check if the file already exists, but there doesn't seem to be a way 
             // to do only that
-            if Object::read_sync(GCS_BUCKET, &gcs_path).is_ok() {
+            if Object::read_sync(&storage.bucket, &gcs_path).is_ok() {
                 warn!("Not uploading build file because there already exists one at {}", url);
             } else {
                 Object::create_sync(
-                    GCS_BUCKET,
+                    &storage.bucket,
                     output.markdown.as_bytes().to_vec(),
                     &gcs_path,
                     "text/markdown; charset=UTF-8",
@@ -187,67 +204,334 @@ fn release_post_build(output: &ParseOutput) -> Result<()> {
     Ok(())
 }
 
-/// Generate code from the input file.
-fn build(args: &ArgMatches) -> Result<()> {
-    let input = args.value_of("INPUT");
-    let codegen_cfg = CodegenConfig {
-        comment_autogen: args
-            .value_of("COMMENT_AUTOGEN")
-            .unwrap_or("true")
-            .parse::<bool>()
-            .unwrap(),
-        add_rustfmt_attributes: true,
-        track_autogen: args.is_present("TRACK_AUTOGEN"),
-        yin: args.is_present("YIN"),
-        release: false,
-    };
+/// Print out where each import will be retrieved from.
+fn print_resolved_imports(
+    project: &Project,
+    input: Option<&str>,
+    parse_cfg: &ParseConfig,
+) -> Result<()> {
+    let found_input = find_file(&project.root, input)?;
+    for (written, resolved) in resolve_import_locations(found_input, parse_cfg)? {
+        if written == resolved {
+            println!("{}", resolved);
+        } else {
+            println!("{} -> {}", written, resolved);
+        }
+    }
+    Ok(())
+}
 
-    generate_code(input, &codegen_cfg)?;
+/// Print out everything that each override file changes.
+fn print_override_changes(
+    project: &Project,
+    input: Option<&str>,
+    parse_cfg: &ParseConfig,
+) -> Result<()> {
+    let found_input = find_file(&project.root, input)?;
+    let layers = explain_overrides(&found_input, parse_cfg)?;
+    if layers.is_empty() {
+        println!("No override files found.");
+    }
+    for layer in layers {
+        let path = layer.path.to_str().unwrap();
+        if layer.changes.is_empty() {
+            println!("{} changes nothing.", path);
+        } else {
+            println!("{}:", path);
+            for change in layer.changes {
+                println!("  {}", change);
+            }
+        }
+    }
     Ok(())
 }
 
-fn release(args: &ArgMatches) -> Result<()> {
-    let input = args.value_of("INPUT");
-    let codegen_cfg = CodegenConfig {
-        comment_autogen: false,
-        add_rustfmt_attributes: true,
-        track_autogen: false,
-        yin: args.is_present("YIN"),
-        release: true,
-    };
+/// Settings for parsing the input file, including the project configuration and override files
+/// given on the commandline.
+fn parse_config(
+    args: &ArgMatches,
+    project: &Project,
+    zamm_cfg: &ZammConfig,
+) -> Result<ParseConfig> {
+    let mut parse_cfg = project.parse_config(zamm_cfg)?;
+    if let Some(overrides) = args.values_of("OVERRIDE") {
+        parse_cfg.overrides.extend(overrides.map(PathBuf::from));
+    }
+    Ok(parse_cfg)
+}
+
+/// The input file specified on the commandline, or else the one from the project configuration.
+fn input_file<'a>(args: &'a ArgMatches, zamm_cfg: &'a ZammConfig) -> Option<&'a str> {
+    args.value_of("INPUT").or(zamm_cfg.input.as_deref())
+}
+
+/// Commandline argument for override files, for every subcommand that parses the input file, so
+/// that they all see the same code.
+fn override_arg() -> Arg<'static, 'static> {
+    Arg::with_name("OVERRIDE")
+        .long("override")
+        .value_name("FILE")
+        .help("Apply another override file after the default ones. Can be given multiple times.")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+}
 
-    release_pre_build()?;
-    let parse_output = generate_code(input, &codegen_cfg)?;
-    release_post_build(&parse_output)?;
+/// Settings for building the intermediate binary, from the project configuration and the
+/// commandline.
+fn build_config(args: &ArgMatches, zamm_cfg: &ZammConfig) -> Result<BuildConfig> {
+    let mut build_cfg = zamm_cfg.build.clone();
+    if let Some(profile) = args.value_of("PROFILE") {
+        build_cfg.profile = Some(profile.to_owned());
+    }
+    build_cfg.offline |= args.is_present("OFFLINE");
+    build_cfg.update_lock |= args.is_present("UPDATE_LOCK");
+    build_cfg.frozen |= args.is_present("FROZEN");
+    if let Some(jobs) = args.value_of("JOBS") {
+        build_cfg.jobs = Some(jobs.parse::<NonZeroU32>().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "--jobs should be a positive number",
+            )
+        })?);
+    }
+    if let Some(features) = args.values_of("FEATURES") {
+        build_cfg.features.extend(features.map(str::to_owned));
+    }
+    if let Some(rustflags) = args.value_of("RUSTFLAGS") {
+        build_cfg.rustflags = Some(rustflags.to_owned());
+    }
+    if let Some(vars) = args.values_of("ENV") {
+        for var in vars {
+            let mut parts = var.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => {
+                    build_cfg.env.insert(name.to_owned(), value.to_owned());
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("--env should be of the form NAME=VALUE, not {}", var),
+                    ))
+                }
+            }
+        }
+    }
+    if let Some(run_args) = args.values_of("ARGS") {
+        build_cfg.args.extend(run_args.map(str::to_owned));
+    }
+    Ok(build_cfg)
+}
+
+/// Run a command on every literate member of the workspace in the current directory, and print
+/// out how it went for each of them.
+fn for_workspace<F>(task: F) -> Result<()>
+where
+    F: Fn(&Project) -> Result<()> + Sync,
+{
+    let workspace = Workspace::load(&Project::current()?.root)?;
+    let report = workspace.run(|member| {
+        println!("Running for workspace member {}...", member.name);
+        task(&member.project)
+    })?;
+    println!("{}", report);
+    match report.failures() {
+        0 => Ok(()),
+        failures => Err(Error::new(
+            ErrorKind::Other,
+            format!("{} workspace members did not succeed", failures),
+        )),
+    }
+}
+
+/// Generate code from the input file, for either the current project or every literate member of
+/// the current workspace.
+fn build(args: &ArgMatches) -> Result<()> {
+    if args.is_present("WORKSPACE") {
+        for_workspace(|project| build_project(args, project))
+    } else {
+        build_project(args, &Project::current()?)
+    }
+}
+
+/// Generate code from the input file of the given project.
+fn build_project(args: &ArgMatches, project: &Project) -> Result<()> {
+    let zamm_cfg = project.config()?;
+    let input = input_file(args, &zamm_cfg);
+    let parse_cfg = parse_config(args, project, &zamm_cfg)?;
+    if args.is_present("PRINT_RESOLVED_IMPORTS") {
+        return print_resolved_imports(project, input, &parse_cfg);
+    }
+    if args.is_present("EXPLAIN_OVERRIDES") {
+        return print_override_changes(project, input, &parse_cfg);
+    }
+    let codegen_cfg = codegen_config(args, &zamm_cfg)?;
+    let build_cfg = build_config(args, &zamm_cfg)?;
+    let (result, build_timings) = timings::record(|| {
+        if args.is_present("DRY_RUN") {
+            generate_code_with_backend(
+                project,
+                input,
+                &codegen_cfg,
+                &parse_cfg,
+                &DryRunBackend { build_cfg },
+            )
+        } else {
+            generate_code_with_config(project, input, &codegen_cfg, &parse_cfg, &build_cfg)
+        }
+    });
+    // timings are still worth seeing when the build fails, to tell where it got to
+    if args.is_present("TIMINGS") || args.is_present("TIMINGS_FORMAT") {
+        if args.value_of("TIMINGS_FORMAT") == Some("json") {
+            build_timings.save(project)?;
+            println!("Timings written to {}", Timings::path(project).display());
+        } else {
+            println!("{}", build_timings.summary());
+        }
+    }
+    result.map(|_| ())
+}
+
+/// Make sure the generated files are up to date with the input file.
+fn check(args: &ArgMatches) -> Result<()> {
+    let project = Project::current()?;
+    let zamm_cfg = project.config()?;
+    let codegen_cfg = if args.is_present("RELEASE") {
+        release_codegen_config(args, &zamm_cfg)
+    } else {
+        codegen_config(args, &zamm_cfg)?
+    };
+    generate_code_with_backend(
+        &project,
+        input_file(args, &zamm_cfg),
+        &codegen_cfg,
+        &parse_config(args, &project, &zamm_cfg)?,
+        &CheckBackend {
+            build_cfg: build_config(args, &zamm_cfg)?,
+        },
+    )?;
     Ok(())
 }
 
-/// Clean all autogenerated files.
-fn clean(_: &ArgMatches) -> Result<()> {
-    commands::clean()?;
+/// Value of a flag that can be given on its own to turn a setting on, or as `--flag=false` to turn
+/// it off. The configured value is used if the flag isn't given at all.
+fn bool_flag(args: &ArgMatches, name: &str, configured: bool) -> bool {
+    if args.is_present(name) {
+        args.value_of(name) != Some("false")
+    } else {
+        configured
+    }
+}
+
+/// Settings for the generated code, from the project configuration and the commandline.
+fn codegen_config(args: &ArgMatches, zamm_cfg: &ZammConfig) -> Result<CodegenConfig> {
+    let defaults = zamm_cfg.codegen_config();
+    Ok(CodegenConfig {
+        comment_autogen: match args.value_of("COMMENT_AUTOGEN") {
+            Some(comment_autogen) => comment_autogen.parse::<bool>().map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "--comment_autogen should be either true or false",
+                )
+            })?,
+            None => defaults.comment_autogen,
+        },
+        track_autogen: bool_flag(args, "TRACK_AUTOGEN", defaults.track_autogen),
+        yin: bool_flag(args, "YIN", defaults.yin),
+        release: false,
+        ..defaults
+    })
+}
+
+/// Settings for the generated code of a release. Only whether it's for Yin can be changed.
+fn release_codegen_config(args: &ArgMatches, zamm_cfg: &ZammConfig) -> CodegenConfig {
+    CodegenConfig::release(bool_flag(args, "YIN", zamm_cfg.codegen_config().yin))
+}
+
+fn release(args: &ArgMatches) -> Result<()> {
+    let project = Project::current()?;
+    let zamm_cfg = project.config()?;
+    let input = input_file(args, &zamm_cfg);
+    let codegen_cfg = release_codegen_config(args, &zamm_cfg);
+
+    release_pre_build(&project)?;
+    let parse_output = generate_code_with_config(
+        &project,
+        input,
+        &codegen_cfg,
+        &parse_config(args, &project, &zamm_cfg)?,
+        &zamm_cfg.build,
+    )?;
+    release_post_build(&parse_output, &zamm_cfg)?;
     Ok(())
 }
 
+/// Clean all autogenerated files, for either the current project or every literate member of the
+/// current workspace.
+fn clean(args: &ArgMatches) -> Result<()> {
+    if args.is_present("WORKSPACE") {
+        for_workspace(commands::clean)
+    } else {
+        commands::clean(&Project::current()?)
+    }
+}
+
+/// Show everything that the input file imports.
+fn imports(args: &ArgMatches) -> Result<()> {
+    let project = Project::current()?;
+    let zamm_cfg = project.config()?;
+    let input = input_file(args, &zamm_cfg);
+    let format = args
+        .value_of("FORMAT")
+        .unwrap_or("text")
+        .parse::<GraphFormat>()?;
+    // don't announce the input file when the output is meant for other tools
+    let found_input = if format == GraphFormat::Text {
+        find_file(&project.root, input)?
+    } else {
+        locate_file(&project.root, input)?
+    };
+    commands::print_imports(
+        &found_input,
+        &parse_config(args, &project, &zamm_cfg)?,
+        format,
+    )
+}
+
+/// Snapshot all imports locally.
+fn vendor(args: &ArgMatches) -> Result<()> {
+    let project = Project::current()?;
+    let zamm_cfg = project.config()?;
+    let found_input = find_file(&project.root, input_file(args, &zamm_cfg))?;
+    commands::vendor(
+        &project,
+        &found_input,
+        &parse_config(args, &project, &zamm_cfg)?,
+    )
+}
+
 /// Run various tests and checks.
 fn test(args: &ArgMatches) -> Result<()> {
     let yang = args.is_present("YANG");
+    // cargo only covers every workspace member when asked to
+    let (fmt_scope, workspace_scope): (&[&str], &[&str]) = if args.is_present("WORKSPACE") {
+        (&["--all"], &["--workspace"])
+    } else {
+        (&[], &[])
+    };
 
     println!("Formatting...");
-    run_command("cargo", &["fmt"])?;
+    run_command("cargo", [&["fmt"], fmt_scope].concat())?;
     println!("Running tests...");
-    run_command("cargo", &["test"])?;
+    run_command("cargo", [&["test"], workspace_scope].concat())?;
     println!("Running lints...");
-    run_command(
-        "cargo",
-        &[
-            "clippy",
-            "--all-features",
-            "--all-targets",
-            "--",
-            "-D",
-            "warnings",
-        ],
-    )?;
+    let clippy_args = [
+        &["clippy", "--all-features", "--all-targets"],
+        workspace_scope,
+        &["--", "-D", "warnings"],
+    ]
+    .concat();
+    run_command("cargo", &clippy_args)?;
     if yang {
         println!("Running yang build...");
         run_command("cargo", &["run", "build"])?;
@@ -275,6 +559,15 @@ fn main() {
                         .help(INPUT_HELP_TEXT)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("WORKSPACE")
+                        .long("workspace")
+                        .conflicts_with("INPUT")
+                        .help(
+                            "Build every crate in the current Cargo workspace that has an input \
+                            file, in dependency order",
+                        ),
+                )
                 .arg(
                     Arg::with_name("COMMENT_AUTOGEN")
                         .short("c")
@@ -290,18 +583,175 @@ fn main() {
                     Arg::with_name("TRACK_AUTOGEN")
                         .short("t")
                         .long("track-autogen")
+                        .value_name("BOOL")
                         .help(
                             "Whether or not we want Cargo to track autogenerated files and \
-                            rebuild when they change. Can result in constant rebuilds.",
-                        ),
+                            rebuild when they change. Can result in constant rebuilds. Use \
+                            --track-autogen=false to override the project configuration.",
+                        )
+                        .takes_value(true)
+                        .min_values(0)
+                        .require_equals(true)
+                        .possible_values(&["true", "false"]),
                 )
                 .arg(
                     Arg::with_name("YIN")
                         .short("y")
                         .long("yin")
-                        .help("Set to generate code for Yin instead"),
+                        .value_name("BOOL")
+                        .help(
+                            "Set to generate code for Yin instead. Use --yin=false to override \
+                            the project configuration.",
+                        )
+                        .takes_value(true)
+                        .min_values(0)
+                        .require_equals(true)
+                        .possible_values(&["true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("PRINT_RESOLVED_IMPORTS")
+                        .long("print-resolved-imports")
+                        .help(
+                            "Print the final location of each import after mirrors are \
+                            applied, without building anything",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("PROFILE")
+                        .long("profile")
+                        .value_name("PROFILE")
+                        .help(
+                            "Cargo profile to build the intermediate codegen binary with, such as \
+                            release",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("OFFLINE")
+                        .long("offline")
+                        .help("Build the intermediate codegen binary without network access"),
+                )
+                .arg(Arg::with_name("FROZEN").long("frozen").help(
+                    "Require the intermediate build's Cargo.lock and dependency cache to \
+                            be up to date",
+                ))
+                .arg(Arg::with_name("UPDATE_LOCK").long("update-lock").help(
+                    "Let Cargo pick new dependency versions for the intermediate build, \
+                            and save them to intermediate.lock",
+                ))
+                .arg(
+                    Arg::with_name("JOBS")
+                        .short("j")
+                        .long("jobs")
+                        .value_name("N")
+                        .help("Number of parallel jobs for the intermediate build")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FEATURES")
+                        .long("features")
+                        .value_name("FEATURES")
+                        .help(
+                            "Features to turn on for the intermediate build, such as \
+                            zamm_yang/some-feature. Can be given multiple times.",
+                        )
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("RUSTFLAGS")
+                        .long("rustflags")
+                        .value_name("FLAGS")
+                        .help("Extra compiler flags for the intermediate build")
+                        .takes_value(true)
+                        .allow_hyphen_values(true),
+                )
+                .arg(
+                    Arg::with_name("ENV")
+                        .long("env")
+                        .value_name("NAME=VALUE")
+                        .help(
+                            "Set an environment variable starting with ZAMM_ for the codegen \
+                            binary. Can be given multiple times.",
+                        )
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("ARGS")
+                        .value_name("ARGS")
+                        .help("Arguments to pass to the codegen binary, given after --")
+                        .multiple(true)
+                        .last(true),
+                )
+                .arg(override_arg())
+                .arg(Arg::with_name("DRY_RUN").long("dry-run").help(
+                    "Show a diff of what the build would change, without changing any \
+                            project files",
+                ))
+                .arg(
+                    Arg::with_name("TIMINGS")
+                        .long("timings")
+                        .help("Show how long each phase of the build took"),
+                )
+                .arg(
+                    Arg::with_name("TIMINGS_FORMAT")
+                        .long("timings-format")
+                        .value_name("FORMAT")
+                        .possible_values(&["text", "json"])
+                        .help(
+                            "Show timings either as a table, or as JSON written to \
+                            .zamm/timings.json. Implies --timings. Defaults to text.",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("EXPLAIN_OVERRIDES")
+                        .long("explain-overrides")
+                        .help(
+                            "Print what each override file changes about the input file, \
+                            without building anything",
+                        ),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("check")
+                .setting(AppSettings::ColoredHelp)
+                .about("Make sure the generated files are up to date with the input file")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .value_name("INPUT")
+                        .help(INPUT_HELP_TEXT)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("YIN")
+                        .short("y")
+                        .long("yin")
+                        .value_name("BOOL")
+                        .help(
+                            "Set to generate code for Yin instead. Use --yin=false to override \
+                            the project configuration.",
+                        )
+                        .takes_value(true)
+                        .min_values(0)
+                        .require_equals(true)
+                        .possible_values(&["true", "false"]),
+                )
+                .arg(
+                    Arg::with_name("OFFLINE")
+                        .long("offline")
+                        .help("Build the intermediate codegen binary without network access"),
+                )
+                .arg(
+                    Arg::with_name("RELEASE")
+                        .long("release")
+                        .help("Check against what `zamm release` would generate instead"),
+                )
+                .arg(override_arg()),
+        )
         .subcommand(
             SubCommand::with_name("release")
                 .setting(AppSettings::ColoredHelp)
@@ -316,13 +766,60 @@ fn main() {
                     Arg::with_name("YIN")
                         .short("y")
                         .long("yin")
-                        .help("Set to generate code for Yin instead"),
-                ),
+                        .value_name("BOOL")
+                        .help(
+                            "Set to generate code for Yin instead. Use --yin=false to override \
+                            the project configuration.",
+                        )
+                        .takes_value(true)
+                        .min_values(0)
+                        .require_equals(true)
+                        .possible_values(&["true", "false"]),
+                )
+                .arg(override_arg()),
         )
         .subcommand(
             SubCommand::with_name("clean")
                 .setting(AppSettings::ColoredHelp)
-                .about("Clean up autogenerated files"),
+                .about("Clean up autogenerated files")
+                .arg(
+                    Arg::with_name("WORKSPACE")
+                        .long("workspace")
+                        .help("Clean every crate in the current Cargo workspace"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("imports")
+                .setting(AppSettings::ColoredHelp)
+                .about("Show the tree of everything that the input file imports")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .value_name("INPUT")
+                        .help(INPUT_HELP_TEXT)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FORMAT")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["text", "json", "dot"])
+                        .help("How to display the import tree. Defaults to text.")
+                        .takes_value(true),
+                )
+                .arg(override_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("vendor")
+                .setting(AppSettings::ColoredHelp)
+                .about("Snapshot all imports locally so that builds need no network access")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .value_name("INPUT")
+                        .help(INPUT_HELP_TEXT)
+                        .takes_value(true),
+                )
+                .arg(override_arg()),
         )
         .subcommand(
             SubCommand::with_name("test")
@@ -333,6 +830,11 @@ fn main() {
                         .short("y")
                         .long("yang")
                         .help("Set when testing yang itself"),
+                )
+                .arg(
+                    Arg::with_name("WORKSPACE")
+                        .long("workspace")
+                        .help("Test every crate in the current Cargo workspace"),
                 ),
         )
         .setting(AppSettings::SubcommandRequiredElseHelp)
@@ -340,10 +842,16 @@ fn main() {
 
     let result = if let Some(build_args) = args.subcommand_matches("build") {
         build(build_args)
+    } else if let Some(check_args) = args.subcommand_matches("check") {
+        check(check_args)
     } else if let Some(release_args) = args.subcommand_matches("release") {
         release(release_args)
     } else if let Some(clean_args) = args.subcommand_matches("clean") {
         clean(clean_args)
+    } else if let Some(imports_args) = args.subcommand_matches("imports") {
+        imports(imports_args)
+    } else if let Some(vendor_args) = args.subcommand_matches("vendor") {
+        vendor(vendor_args)
     } else if let Some(test_args) = args.subcommand_matches("test") {
         test(test_args)
     } else {
@@ -0,0 +1,89 @@
+use serde::Deserialize;
+use std::io::{Error, ErrorKind, Result};
+
+/// Line that front matter starts and ends with.
+pub const FRONT_MATTER_DELIMITER: &str = "+++";
+
+/// Settings given in TOML at the very start of an input file, between two `+++` lines. These take
+/// precedence over the project configuration.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct FrontMatter {
+    /// The template that wraps the literate code in a main function.
+    pub template: Option<String>,
+}
+
+/// Split the TOML front matter off of the rest of the markdown, if there is any.
+fn split_front_matter(markdown: &str) -> Option<&str> {
+    let mut lines = markdown.split('\n');
+    if lines.next()?.trim_end() != FRONT_MATTER_DELIMITER {
+        return None;
+    }
+    let start = markdown.find('\n')? + 1;
+    let mut end = start;
+    for line in lines {
+        if line.trim_end() == FRONT_MATTER_DELIMITER {
+            return Some(&markdown[start..end]);
+        }
+        end += line.len() + 1;
+    }
+    None
+}
+
+/// Parse the front matter of the given markdown. Markdown without front matter gets the defaults.
+pub fn parse_front_matter(markdown: &str) -> Result<FrontMatter> {
+    match split_front_matter(markdown) {
+        Some(front_matter) => toml::from_str(front_matter).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid front matter: {}", e),
+            )
+        }),
+        None => Ok(FrontMatter::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_front_matter() {
+        assert_eq!(
+            parse_front_matter(indoc! {r#"
+                +++
+                template = "plain"
+                +++
+
+                # Book
+            "#})
+            .unwrap(),
+            FrontMatter {
+                template: Some("plain".to_owned())
+            }
+        );
+    }
+
+    #[test]
+    fn test_no_front_matter() {
+        assert_eq!(
+            parse_front_matter("# Book\n\n+++\ntemplate = \"plain\"\n+++\n").unwrap(),
+            FrontMatter::default()
+        );
+        assert_eq!(
+            parse_front_matter("+++\ntemplate = \"plain\"\n").unwrap(),
+            FrontMatter::default()
+        );
+    }
+
+    #[test]
+    fn test_invalid_front_matter() {
+        assert_eq!(
+            parse_front_matter("+++\ntemplate = plain\n+++\n")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
@@ -1,73 +1,833 @@
-use super::{extract_code, CodeExtraction};
-use colored::*;
-use path_abs::{PathAbs, PathInfo};
-use std::fs::read_to_string;
+use super::credentials::redact_url;
+use super::import_error::{ImportError, ImportErrorKind};
+use super::markdown::{extract_selected, Selector};
+use super::{extract_code, CodeExtraction, ParseConfig};
+use crate::build_script;
+use crate::checksum::sha256_hex;
+use crate::warn;
+use reqwest::{StatusCode, Url};
+use serde::Serialize;
+use std::env;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
 use std::io;
-use std::io::{Error, ErrorKind};
-use std::path::Path;
-
-async fn download(url: &str) -> io::Result<CodeExtraction> {
-    println!("Downloading import from {}", url);
-    match reqwest::get(url).await.unwrap().error_for_status() {
-        Ok(response) => {
-            let text = response.text().await.unwrap();
-            Ok(extract_code(&text))
-        }
-        Err(_) => {
-            let msg = format!(
-                "{}",
-                format!("Unable to download build dependency from {}", url)
-                    .red()
-                    .bold()
-            );
-            Err(io::Error::new(io::ErrorKind::NotFound, msg))
+use std::path::{Component, Path, PathBuf};
+use tokio::runtime::Runtime;
+
+/// Environment variable that, when set, makes network imports come only from the local cache.
+pub const ZAMM_OFFLINE_VAR: &str = "ZAMM_OFFLINE";
+
+/// A single import entry, as written in a `zamm` code block.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ImportSpec {
+    /// Where to retrieve the import from. Can be either a URL or a local path.
+    pub location: String,
+    /// The SHA-256 checksum that the imported file is expected to have, if it is pinned.
+    pub sha256: Option<String>,
+    /// The parts of the imported file to take code from. Everything is taken if this is empty.
+    pub selectors: Vec<Selector>,
+    /// The name that override files can refer to this import by, if it was given one explicitly.
+    pub name: Option<String>,
+}
+
+impl ImportSpec {
+    /// Parse an import entry. Options go after a `#`, as `key=value` pairs separated by `&`.
+    /// `sha256` pins the import to a checksum, while `section` and `chunk` (which may be repeated)
+    /// restrict the import to the code under a heading path or in a named code block. `name` sets
+    /// the name that the import is known by in override files.
+    pub fn parse(entry: &str) -> Self {
+        let mut parts = entry.trim().splitn(2, '#');
+        let mut spec = Self {
+            location: parts.next().unwrap_or("").trim().to_owned(),
+            sha256: None,
+            selectors: vec![],
+            name: None,
+        };
+        let options = parts.next().unwrap_or("");
+        for option in options.split('&').filter(|o| !o.trim().is_empty()) {
+            let mut key_value = option.splitn(2, '=');
+            match (key_value.next().unwrap().trim(), key_value.next()) {
+                ("sha256", Some(checksum)) => spec.sha256 = Some(checksum.trim().to_lowercase()),
+                ("section", Some(path)) => spec
+                    .selectors
+                    .push(Selector::Section(path.trim().to_owned())),
+                ("chunk", Some(name)) => {
+                    spec.selectors.push(Selector::Chunk(name.trim().to_owned()))
+                }
+                ("name", Some(name)) => spec.name = Some(name.trim().to_owned()),
+                _ => warn!(
+                    "Ignoring unrecognized option \"{}\" for import {}",
+                    option, spec.location
+                ),
+            }
+        }
+        spec
+    }
+
+    /// The name that identifies this import across versions and locations. This is the explicitly
+    /// given name if there is one, or else `<org>/<book>` for locations of the form
+    /// `.../books/<org>/<book>/<version>/...`. Failing that, the location itself is used.
+    pub fn identity(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let segments: Vec<&str> = self.location.split('/').collect();
+        match segments.iter().position(|s| *s == "books") {
+            Some(i) if i + 3 < segments.len() => format!("{}/{}", segments[i + 1], segments[i + 2]),
+            _ => self.location.clone(),
         }
     }
+
+    /// Whether or not this import needs to be downloaded.
+    pub fn is_network(&self) -> bool {
+        self.location.starts_with("http")
+    }
 }
 
-fn load(local_filename: &str) -> io::Result<CodeExtraction> {
-    println!("Importing local file {}", local_filename);
-    let path = PathAbs::new(Path::new(local_filename))?;
-    if path.exists() {
-        Ok(extract_code(&read_to_string(&local_filename)?))
+impl fmt::Display for ImportSpec {
+    /// Write the spec back out as an import entry.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut options = vec![];
+        if let Some(checksum) = &self.sha256 {
+            options.push(format!("sha256={}", checksum));
+        }
+        for selector in &self.selectors {
+            options.push(match selector {
+                Selector::Section(path) => format!("section={}", path),
+                Selector::Chunk(name) => format!("chunk={}", name),
+            });
+        }
+        if let Some(name) = &self.name {
+            options.push(format!("name={}", name));
+        }
+        write!(f, "{}", self.location)?;
+        if !options.is_empty() {
+            write!(f, "#{}", options.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Make sure that the import contents match the checksum they were pinned to, if any.
+fn verify(spec: &ImportSpec, contents: &str, chain: &[String]) -> Result<(), ImportError> {
+    if let Some(expected) = &spec.sha256 {
+        let actual = sha256_hex(contents.as_bytes());
+        if &actual != expected {
+            let kind = ImportErrorKind::Integrity {
+                expected: expected.clone(),
+                actual,
+            };
+            return Err(ImportError::new(kind, &redact_url(&spec.location), chain));
+        }
+    }
+    Ok(())
+}
+
+/// Whether or not the request failed because a secure connection couldn't be set up. The TLS
+/// backend errors aren't exposed directly by reqwest, so we have to go by their descriptions.
+fn is_tls_failure(error: &reqwest::Error) -> bool {
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let description = cause.to_string().to_lowercase();
+        if ["certificate", "tls", "ssl", "handshake"]
+            .iter()
+            .any(|keyword| description.contains(keyword))
+        {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
+
+fn request_failure(error: reqwest::Error, url: &str, chain: &[String]) -> ImportError {
+    let kind = if is_tls_failure(&error) {
+        ImportErrorKind::Tls
+    } else if error.is_decode() {
+        ImportErrorKind::Decode
     } else {
-        Err(Error::new(
-            ErrorKind::NotFound,
-            format!("No import file found at {}", local_filename),
-        ))
+        ImportErrorKind::Network
+    };
+    ImportError::new(kind, url, chain).with_detail(error)
+}
+
+async fn fetch(
+    url: &str,
+    chain: &[String],
+    parse_cfg: &ParseConfig,
+) -> Result<String, ImportError> {
+    let display_url = redact_url(url);
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(credential) = Url::parse(url)
+        .ok()
+        .and_then(|u| parse_cfg.credentials.for_url(&u).cloned())
+    {
+        request = credential.authenticate(request);
+    }
+    // reqwest strips any credentials embedded in the URL before using it in its own errors
+    let response = request
+        .send()
+        .await
+        .map_err(|e| request_failure(e, &display_url, chain))?;
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Err(ImportError::new(
+            ImportErrorKind::NotFound,
+            &display_url,
+            chain,
+        ));
+    } else if !status.is_success() {
+        let kind = ImportErrorKind::HttpStatus(status.as_u16());
+        return Err(ImportError::new(kind, &display_url, chain));
     }
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| request_failure(e, &display_url, chain))?;
+    String::from_utf8(bytes.to_vec())
+        .map_err(|e| ImportError::new(ImportErrorKind::Decode, &display_url, chain).with_detail(e))
 }
 
-/// Add imported code to CodeExtraction.
-pub fn retrieve_imports(extraction: &CodeExtraction) -> io::Result<CodeExtraction> {
-    let (network_imports, local_imports): (Vec<&str>, Vec<&str>) = extraction
-        .imports
-        .iter()
-        .filter(|i| !i.is_empty())
-        .map(|i| i.as_str())
-        .partition(|i| i.starts_with("http"));
+async fn download(
+    spec: &ImportSpec,
+    chain: &[String],
+    parse_cfg: &ParseConfig,
+) -> Result<(String, ImportOrigin), ImportError> {
+    let url = spec.location.as_str();
+    let display_url = redact_url(url);
+    let cached = || parse_cfg.cache.as_ref().and_then(|c| c.read(&display_url));
+    build_script::rerun_if_env_changed(ZAMM_OFFLINE_VAR);
+    if env::var_os(ZAMM_OFFLINE_VAR).is_some() {
+        if !parse_cfg.quiet {
+            println!("Using cached import of {}", display_url);
+        }
+        let contents = cached().ok_or_else(|| {
+            ImportError::new(ImportErrorKind::Network, &display_url, chain).with_detail(format!(
+                "{} is set, but there is no cached copy",
+                ZAMM_OFFLINE_VAR
+            ))
+        })?;
+        verify(spec, &contents, chain)?;
+        return Ok((contents, ImportOrigin::Cache));
+    }
 
-    let network_futures = network_imports.into_iter().map(download);
+    if !parse_cfg.quiet {
+        println!("Downloading import from {}", display_url);
+    }
+    match fetch(url, chain, parse_cfg).await {
+        Ok(contents) => {
+            verify(spec, &contents, chain)?;
+            if let Some(cache) = &parse_cfg.cache {
+                if let Err(e) = cache.store(&display_url, &contents) {
+                    warn!("Unable to cache import from {}: {}", display_url, e);
+                }
+            }
+            Ok((contents, ImportOrigin::Network))
+        }
+        Err(e) if e.is_connectivity_failure() => match cached() {
+            Some(contents) => {
+                warn!("{}\nFalling back to the cached copy instead.", e);
+                verify(spec, &contents, chain)?;
+                Ok((contents, ImportOrigin::Cache))
+            }
+            None => Err(e),
+        },
+        Err(e) => Err(e),
+    }
+}
 
-    let mut final_extraction = CodeExtraction::default();
-    let imports_involved = !extraction.imports.is_empty();
-    let mut rt = tokio::runtime::Runtime::new().unwrap();
-    rt.block_on(async {
-        if imports_involved {
-            final_extraction.rust += "zamm_yang::helper::start_imports();\n";
+fn load(
+    spec: &ImportSpec,
+    chain: &[String],
+    parse_cfg: &ParseConfig,
+) -> Result<String, ImportError> {
+    if !parse_cfg.quiet {
+        println!("Importing local file {}", spec.location);
+    }
+    build_script::rerun_if_changed(&spec.location);
+    let contents = fs::read_to_string(&spec.location).map_err(|e| {
+        let kind = match e.kind() {
+            io::ErrorKind::InvalidData => ImportErrorKind::Decode,
+            _ => ImportErrorKind::NotFound,
+        };
+        let error = ImportError::new(kind, &spec.location, chain);
+        if e.kind() == io::ErrorKind::NotFound {
+            error // the message already says as much
+        } else {
+            error.with_detail(e)
         }
-        for local_import in local_imports {
-            final_extraction.rust += &load(local_import)?.rust;
+    })?;
+    verify(spec, &contents, chain)?;
+    Ok(contents)
+}
+
+/// Clean up a path without touching the filesystem, by dropping `.` components and resolving `..`
+/// ones against the components before them.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
         }
-        for future_extraction in network_futures {
-            final_extraction.rust += &future_extraction.await?.rust;
+    }
+    normalized
+}
+
+/// Relative path from one directory to another, both of which must be absolute.
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut path: PathBuf = from
+        .components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .collect();
+    path.extend(to.components().skip(common));
+    path
+}
+
+/// Where an import is actually located. Relative locations are resolved against the importing
+/// file, which may itself be a URL, so that the same import always refers to the same file no
+/// matter where zamm is run from. An importer given as a relative path is taken to be relative to
+/// the project root. Local locations always come out as normalized absolute paths.
+pub fn resolve_location(location: &str, importer: &str, project_root: &Path) -> String {
+    if location.starts_with("http") {
+        return location.to_owned();
+    }
+    if importer.starts_with("http") && !Path::new(location).is_absolute() {
+        if let Ok(url) = Url::parse(importer).and_then(|u| u.join(location)) {
+            return url.to_string();
         }
-        if imports_involved {
-            final_extraction.rust += "zamm_yang::helper::end_imports();\n";
+    }
+    let importer_dir = Path::new(importer)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    // joining onto an absolute path replaces it entirely
+    let base = env::current_dir()
+        .unwrap_or_default()
+        .join(project_root)
+        .join(importer_dir);
+    normalize(&base.join(location)).to_str().unwrap().to_owned()
+}
+
+/// Parse the imports of the extraction, point them at their mirrors, and resolve them against the
+/// file that they were imported from. Returns each import as it was written alongside its resolved
+/// form.
+pub fn resolve_specs(
+    extraction: &CodeExtraction,
+    importer: &str,
+    parse_cfg: &ParseConfig,
+) -> Vec<(String, ImportSpec)> {
+    extraction
+        .imports
+        .iter()
+        .filter(|i| !i.is_empty())
+        .map(|i| {
+            let mut spec = ImportSpec::parse(i);
+            spec.location = resolve_location(
+                &parse_cfg.mirrors.resolve(&spec.location),
+                importer,
+                &parse_cfg.project_root,
+            );
+            (i.to_owned(), spec)
+        })
+        .collect()
+}
+
+/// Where the contents of an import ended up coming from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportOrigin {
+    /// The import is a local file.
+    Local,
+    /// The import was freshly downloaded.
+    Network,
+    /// The import was downloaded at some earlier point, and retrieved from the cache this time.
+    Cache,
+}
+
+/// Retrieve the contents of a single import, wherever it may be.
+fn retrieve(
+    spec: &ImportSpec,
+    chain: &[String],
+    parse_cfg: &ParseConfig,
+    rt: &mut Runtime,
+) -> Result<(String, ImportOrigin), ImportError> {
+    if spec.is_network() {
+        rt.block_on(download(spec, chain, parse_cfg))
+    } else {
+        Ok((load(spec, chain, parse_cfg)?, ImportOrigin::Local))
+    }
+}
+
+/// An import, along with everything that it imports in turn.
+#[derive(Debug, Clone)]
+pub struct ImportNode {
+    /// The import entry, exactly as it was written in the importing file.
+    pub written: String,
+    /// The import entry, after mirrors have been applied.
+    pub spec: ImportSpec,
+    /// The contents of the imported file.
+    pub contents: String,
+    /// Where the contents of the imported file came from.
+    pub origin: ImportOrigin,
+    /// The imports of the imported file.
+    pub children: Vec<ImportNode>,
+}
+
+fn resolve_children(
+    extraction: &CodeExtraction,
+    importer: &str,
+    chain: &[String],
+    parse_cfg: &ParseConfig,
+    rt: &mut Runtime,
+) -> Result<Vec<ImportNode>, ImportError> {
+    let mut nodes = vec![];
+    for (written, spec) in resolve_specs(extraction, importer, parse_cfg) {
+        let display_location = redact_url(&spec.location);
+        if chain.contains(&display_location) {
+            warn!("Skipping circular import of {}", display_location);
+            continue;
         }
-        final_extraction.rust += &extraction.rust;
-        final_extraction.toml = extraction.toml.clone();
-        Ok::<(), io::Error>(())
-    })?;
+        let (contents, origin) = retrieve(&spec, chain, parse_cfg, rt)?;
+        let mut child_chain = chain.to_vec();
+        child_chain.push(display_location);
+        let children = resolve_children(
+            &extract_code(&contents),
+            &spec.location,
+            &child_chain,
+            parse_cfg,
+            rt,
+        )?;
+        nodes.push(ImportNode {
+            written,
+            spec,
+            contents,
+            origin,
+            children,
+        });
+    }
+    Ok(nodes)
+}
+
+/// Retrieve the imports of the extraction, along with all the imports of those imports.
+/// `importer` is the file that the top-level imports were declared in.
+pub fn resolve_import_tree(
+    extraction: &CodeExtraction,
+    importer: &str,
+    parse_cfg: &ParseConfig,
+) -> io::Result<Vec<ImportNode>> {
+    let mut rt = Runtime::new()?;
+    let importer = resolve_location(importer, "", &parse_cfg.project_root);
+    Ok(resolve_children(
+        extraction,
+        &importer,
+        &[redact_url(&importer)],
+        parse_cfg,
+        &mut rt,
+    )?)
+}
+
+/// Add imported code to CodeExtraction, separately from the code of the importing file. `importer` is the file that the imports were declared in.
+///
+/// Import failures are returned as an `ImportError` wrapped inside the IO error.
+pub fn retrieve_imports(
+    extraction: &CodeExtraction,
+    importer: &str,
+    parse_cfg: &ParseConfig,
+) -> io::Result<CodeExtraction> {
+    let importer = resolve_location(importer, "", &parse_cfg.project_root);
+    let specs: Vec<ImportSpec> = resolve_specs(extraction, &importer, parse_cfg)
+        .into_iter()
+        .map(|(_, spec)| spec)
+        .collect();
+    let (network_imports, local_imports): (Vec<&ImportSpec>, Vec<&ImportSpec>) =
+        specs.iter().partition(|s| s.is_network());
+    let chain = vec![redact_url(&importer)];
+
+    let mut final_extraction = CodeExtraction::default();
+    let mut rt = Runtime::new()?;
+    for spec in local_imports.into_iter().chain(network_imports) {
+        let (contents, _) = retrieve(spec, &chain, parse_cfg, &mut rt)?;
+        let selected = extract_selected(&contents, &spec.selectors).map_err(|missing| {
+            let kind = ImportErrorKind::MissingSelection(missing.to_string());
+            ImportError::new(kind, &redact_url(&spec.location), &chain)
+        })?;
+        final_extraction.imported_rust += &selected.rust;
+    }
+    final_extraction.rust = extraction.rust.clone();
+    final_extraction.toml = extraction.toml.clone();
     Ok(final_extraction)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{Credential, ImportCache};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("/project"), Path::new("/project/zamm-vendor")),
+            Path::new("zamm-vendor")
+        );
+        assert_eq!(
+            relative_path(
+                Path::new("/project/docs"),
+                Path::new("/project/zamm-vendor")
+            ),
+            Path::new("../zamm-vendor")
+        );
+    }
+
+    /// Answer a single HTTP request from a local stand-in server, and return the URL to send the
+    /// request to.
+    fn serve_once<F>(respond: F) -> String
+    where
+        F: FnOnce(&str) -> String + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let size = stream.read(&mut request).unwrap_or(0);
+            let response = respond(&String::from_utf8_lossy(&request[..size]));
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{}/yin.md", address)
+    }
+
+    /// Serve the book only to requests with the right authorization header.
+    fn serve_authenticated(expected_header: &'static str) -> String {
+        serve_once(move |request| {
+            if request
+                .to_lowercase()
+                .contains(&expected_header.to_lowercase())
+            {
+                "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nSecret book".to_owned()
+            } else {
+                "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_owned()
+            }
+        })
+    }
+
+    fn temp_file(dir: &TempDir, name: &str, contents: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn download_now(spec: &ImportSpec, parse_cfg: &ParseConfig) -> Result<String, ImportError> {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(download(spec, &["yin.md".to_owned()], parse_cfg))
+            .map(|(contents, _)| contents)
+    }
+
+    #[test]
+    fn test_parse_spec_plain() {
+        assert_eq!(
+            ImportSpec::parse(" https://api.zamm.dev/yin.md "),
+            ImportSpec {
+                location: "https://api.zamm.dev/yin.md".to_owned(),
+                sha256: None,
+                selectors: vec![],
+                name: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_spec_checksum() {
+        assert_eq!(
+            ImportSpec::parse("../yin.md#sha256=ABCD"),
+            ImportSpec {
+                location: "../yin.md".to_owned(),
+                sha256: Some("abcd".to_owned()),
+                selectors: vec![],
+                name: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_spec_selectors() {
+        assert_eq!(
+            ImportSpec::parse("../yin.md#section=Book/Helpers&chunk=setup&chunk=teardown"),
+            ImportSpec {
+                location: "../yin.md".to_owned(),
+                sha256: None,
+                selectors: vec![
+                    Selector::Section("Book/Helpers".to_owned()),
+                    Selector::Chunk("setup".to_owned()),
+                    Selector::Chunk("teardown".to_owned()),
+                ],
+                name: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_spec_round_trip() {
+        let entry = "https://api.zamm.dev/yin.md#sha256=abcd&section=Book/Helpers&chunk=setup&name=zamm/yin";
+        assert_eq!(ImportSpec::parse(entry).to_string(), entry);
+    }
+
+    #[test]
+    fn test_spec_identity() {
+        assert_eq!(
+            ImportSpec::parse("https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md").identity(),
+            "zamm/yin"
+        );
+        assert_eq!(
+            ImportSpec::parse("../yin/yin.md#name=zamm/yin").identity(),
+            "zamm/yin"
+        );
+        assert_eq!(
+            ImportSpec::parse("../yin/yin.md").identity(),
+            "../yin/yin.md"
+        );
+    }
+
+    #[test]
+    fn test_resolve_location() {
+        assert_eq!(
+            resolve_location("../yang/yang.md", "/books/yin/yin.md", Path::new("")),
+            "/books/yang/yang.md"
+        );
+        assert_eq!(
+            resolve_location("./helpers.md", "/books/yin/yin.md", Path::new("")),
+            "/books/yin/helpers.md"
+        );
+        assert_eq!(
+            resolve_location("/srv/zamm/yin.md", "/books/yin/yin.md", Path::new("")),
+            "/srv/zamm/yin.md"
+        );
+        assert_eq!(
+            resolve_location(
+                "helpers.md",
+                "https://api.zamm.dev/v1/books/zamm/yin/yin.md",
+                Path::new("")
+            ),
+            "https://api.zamm.dev/v1/books/zamm/yin/helpers.md"
+        );
+        assert_eq!(
+            resolve_location(
+                "https://api.zamm.dev/yin.md",
+                "/books/yin/yin.md",
+                Path::new("")
+            ),
+            "https://api.zamm.dev/yin.md"
+        );
+        // relative importers are relative to the project root, which is relative to the working
+        // directory
+        assert_eq!(
+            resolve_location("helpers.md", "yin/yin.md", Path::new("/books")),
+            "/books/yin/helpers.md"
+        );
+        assert_eq!(
+            resolve_location("yin.md", "", Path::new("")),
+            env::current_dir().unwrap().join("yin.md").to_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_retrieve_selected() {
+        let dir = tempfile::tempdir().unwrap();
+        let book = temp_file(
+            &dir,
+            "selected.md",
+            "# Helpers\n\n```rust\nlet helper = 1;\n```\n\n# Main\n\n```rust\nlet main = 2;\n```\n",
+        );
+        let extraction = CodeExtraction {
+            imports: vec![format!("{}#section=Helpers", book.to_str().unwrap())],
+            ..CodeExtraction::default()
+        };
+        let retrieved = retrieve_imports(&extraction, "yin.md", &ParseConfig::default()).unwrap();
+        assert_eq!(retrieved.imported_rust, "let helper = 1;\n");
+        assert!(retrieved.rust.is_empty());
+    }
+
+    #[test]
+    fn test_retrieve_missing_section() {
+        let dir = tempfile::tempdir().unwrap();
+        let book = temp_file(&dir, "missing-section.md", "# Helpers\n");
+        let extraction = CodeExtraction {
+            imports: vec![format!("{}#section=Main", book.to_str().unwrap())],
+            ..CodeExtraction::default()
+        };
+        let error = retrieve_imports(&extraction, "yin.md", &ParseConfig::default()).unwrap_err();
+        let import_error = ImportError::from_io_error(&error).unwrap();
+        assert_eq!(
+            import_error.kind,
+            ImportErrorKind::MissingSelection("section \"Main\"".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_import_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("leaves")).unwrap();
+        let leaf = temp_file(&dir, "leaves/leaf.md", "```rust\nlet leaf = 1;\n```\n");
+        // nested imports are relative to the importing file, not to the working directory
+        let branch = temp_file(&dir, "branch.md", "```zamm\nleaves/./leaf.md\n```\n");
+        let extraction = CodeExtraction {
+            imports: vec![branch.to_str().unwrap().to_owned()],
+            ..CodeExtraction::default()
+        };
+        let tree = resolve_import_tree(&extraction, "yin.md", &ParseConfig::default()).unwrap();
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].spec.location, branch.to_str().unwrap());
+        assert_eq!(tree[0].children.len(), 1);
+        assert_eq!(tree[0].children[0].spec.location, leaf.to_str().unwrap());
+        assert_eq!(tree[0].children[0].origin, ImportOrigin::Local);
+        assert!(tree[0].children[0].children.is_empty());
+    }
+
+    #[test]
+    fn test_import_tree_circular() {
+        let dir = tempfile::tempdir().unwrap();
+        // the self-import is only recognized as such once it's resolved against the importer
+        let path = temp_file(&dir, "circular.md", "```zamm\nsub/../circular.md\n```\n");
+        let extraction = CodeExtraction {
+            imports: vec![path.to_str().unwrap().to_owned()],
+            ..CodeExtraction::default()
+        };
+        let tree = resolve_import_tree(&extraction, "yin.md", &ParseConfig::default()).unwrap();
+        assert_eq!(tree.len(), 1);
+        assert!(tree[0].children.is_empty());
+    }
+
+    #[test]
+    fn test_local_not_found() {
+        let spec = ImportSpec::parse("definitely/not/here.md");
+        let error = load(&spec, &["yin.md".to_owned()], &ParseConfig::default()).unwrap_err();
+        assert_eq!(error.kind, ImportErrorKind::NotFound);
+        assert_eq!(error.url, "definitely/not/here.md");
+        assert_eq!(error.chain, vec!["yin.md".to_owned()]);
+        assert_eq!(
+            error.to_string(),
+            "No import file found at definitely/not/here.md\n  imported by yin.md"
+        );
+    }
+
+    #[test]
+    fn test_local_integrity() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = temp_file(&dir, "integrity.md", "Some book");
+        let location = path.to_str().unwrap();
+        let good = ImportSpec::parse(&format!("{}#sha256={}", location, sha256_hex(b"Some book")));
+        assert_eq!(
+            load(&good, &[], &ParseConfig::default()).unwrap(),
+            "Some book"
+        );
+
+        let bad = ImportSpec::parse(&format!("{}#sha256=1234", location));
+        match load(&bad, &[], &ParseConfig::default()).unwrap_err().kind {
+            ImportErrorKind::Integrity { expected, .. } => assert_eq!(expected, "1234"),
+            other => panic!("Unexpected error kind {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_download_not_found() {
+        let url = serve_once(|_| "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned());
+        let error = download_now(&ImportSpec::parse(&url), &ParseConfig::default()).unwrap_err();
+        assert_eq!(error.kind, ImportErrorKind::NotFound);
+        assert_eq!(error.url, url);
+    }
+
+    #[test]
+    fn test_download_http_status() {
+        let url = serve_once(|_| {
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n".to_owned()
+        });
+        let error = download_now(&ImportSpec::parse(&url), &ParseConfig::default()).unwrap_err();
+        assert_eq!(error.kind, ImportErrorKind::HttpStatus(503));
+    }
+
+    #[test]
+    fn test_download_network_failure_uses_cache() {
+        // bind and immediately drop a listener to get a port that nothing is listening on
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let url = format!("http://{}/yin.md", address);
+        let spec = ImportSpec::parse(&url);
+        let mut parse_cfg = ParseConfig::default();
+        assert_eq!(
+            download_now(&spec, &parse_cfg).unwrap_err().kind,
+            ImportErrorKind::Network
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ImportCache {
+            dir: dir.path().join("cache"),
+        };
+        cache.store(&url, "Cached book").unwrap();
+        parse_cfg.cache = Some(cache.clone());
+        assert_eq!(download_now(&spec, &parse_cfg).unwrap(), "Cached book");
+    }
+
+    #[test]
+    fn test_download_unauthenticated() {
+        let url = serve_authenticated("Authorization: Bearer secret-token");
+        let error = download_now(&ImportSpec::parse(&url), &ParseConfig::default()).unwrap_err();
+        assert_eq!(error.kind, ImportErrorKind::HttpStatus(401));
+    }
+
+    #[test]
+    fn test_download_bearer_token() {
+        let url = serve_authenticated("Authorization: Bearer secret-token");
+        let mut parse_cfg = ParseConfig::default();
+        parse_cfg
+            .credentials
+            .insert("127.0.0.1", Credential::Bearer("secret-token".to_owned()));
+        assert_eq!(
+            download_now(&ImportSpec::parse(&url), &parse_cfg).unwrap(),
+            "Secret book"
+        );
+    }
+
+    #[test]
+    fn test_download_basic_auth_in_url() {
+        // base64 encoding of "me:secret"
+        let url = serve_authenticated("Authorization: Basic bWU6c2VjcmV0");
+        let url_with_auth = url.replace("http://", "http://me:secret@");
+        assert_eq!(
+            download_now(&ImportSpec::parse(&url_with_auth), &ParseConfig::default()).unwrap(),
+            "Secret book"
+        );
+    }
+
+    #[test]
+    fn test_error_hides_credentials() {
+        let url = serve_once(|_| "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n".to_owned());
+        let url_with_auth = url.replace("http://", "http://me:secret@");
+        let error =
+            download_now(&ImportSpec::parse(&url_with_auth), &ParseConfig::default()).unwrap_err();
+        assert_eq!(error.kind, ImportErrorKind::HttpStatus(403));
+        assert!(!error.to_string().contains("secret"));
+    }
+
+    #[test]
+    fn test_into_io_error() {
+        let import_error = ImportError::new(ImportErrorKind::Decode, "yin.md", &[]);
+        let io_error: io::Error = import_error.clone().into();
+        assert_eq!(io_error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(ImportError::from_io_error(&io_error), Some(&import_error));
+    }
+}
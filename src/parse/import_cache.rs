@@ -0,0 +1,38 @@
+use crate::checksum::sha256_hex;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Local copies of previously downloaded imports, so that builds can still proceed when the
+/// network is unavailable.
+#[derive(Debug, Clone)]
+pub struct ImportCache {
+    /// Directory that cached imports are stored in.
+    pub dir: PathBuf,
+}
+
+impl ImportCache {
+    /// The cache shared by all projects of the current user, if the platform has a cache
+    /// directory.
+    pub fn user_cache() -> Option<Self> {
+        dirs::cache_dir().map(|d| Self {
+            dir: d.join("zamm").join("imports"),
+        })
+    }
+
+    /// Where the cached copy of the import at the given URL lives.
+    pub fn path_for(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{}.md", sha256_hex(url.as_bytes())))
+    }
+
+    /// Retrieve the cached copy of the import at the given URL, if it exists.
+    pub fn read(&self, url: &str) -> Option<String> {
+        fs::read_to_string(self.path_for(url)).ok()
+    }
+
+    /// Save a freshly downloaded import for future offline use.
+    pub fn store(&self, url: &str, contents: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path_for(url), contents)
+    }
+}
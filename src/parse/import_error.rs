@@ -0,0 +1,129 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// The different ways in which retrieving an import can fail.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ImportErrorKind {
+    /// There is no file at the import location.
+    NotFound,
+    /// The server responded with an unsuccessful HTTP status code other than 404.
+    HttpStatus(u16),
+    /// The server could not be reached, or the connection broke off midway.
+    Network,
+    /// A secure connection to the server could not be established.
+    Tls,
+    /// The imported file is not valid UTF-8 text.
+    Decode,
+    /// The imported file does not match the checksum it was pinned to.
+    Integrity {
+        /// The SHA-256 checksum that the import was pinned to.
+        expected: String,
+        /// The SHA-256 checksum of what was actually retrieved.
+        actual: String,
+    },
+    /// The imported file has no section or chunk matching what the import asked for.
+    MissingSelection(String),
+}
+
+/// Failure to retrieve an imported file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ImportError {
+    /// What went wrong.
+    pub kind: ImportErrorKind,
+    /// The URL or local path that the import was being retrieved from.
+    pub url: String,
+    /// The files that led to this import, starting from the main input file.
+    pub chain: Vec<String>,
+    /// Lower-level details about the failure, if there are any.
+    pub detail: Option<String>,
+}
+
+impl ImportError {
+    /// Create a new import error for the given import location.
+    pub fn new(kind: ImportErrorKind, url: &str, chain: &[String]) -> Self {
+        Self {
+            kind,
+            url: url.to_owned(),
+            chain: chain.to_vec(),
+            detail: None,
+        }
+    }
+
+    /// Attach lower-level details to this error.
+    pub fn with_detail(mut self, detail: impl fmt::Display) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// Whether or not a cached copy of the import could stand in for the failed retrieval.
+    pub fn is_connectivity_failure(&self) -> bool {
+        matches!(self.kind, ImportErrorKind::Network | ImportErrorKind::Tls)
+    }
+
+    /// Retrieve the import error wrapped inside an IO error, if there is one. Use this to handle
+    /// import failures returned by functions such as `generate_code`.
+    pub fn from_io_error(error: &io::Error) -> Option<&ImportError> {
+        error
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<ImportError>())
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ImportErrorKind::NotFound => write!(f, "No import file found at {}", self.url)?,
+            ImportErrorKind::HttpStatus(status) => write!(
+                f,
+                "Unable to download import from {}: server responded with HTTP {}",
+                self.url, status
+            )?,
+            ImportErrorKind::Network => write!(
+                f,
+                "Unable to download import from {}: network failure",
+                self.url
+            )?,
+            ImportErrorKind::Tls => write!(
+                f,
+                "Unable to download import from {}: secure connection failed",
+                self.url
+            )?,
+            ImportErrorKind::Decode => {
+                write!(f, "Import from {} is not valid UTF-8 text", self.url)?
+            }
+            ImportErrorKind::Integrity { expected, actual } => write!(
+                f,
+                "Import from {} failed its integrity check: expected SHA-256 {}, but got {}",
+                self.url, expected, actual
+            )?,
+            ImportErrorKind::MissingSelection(selection) => {
+                write!(f, "Import from {} has no {}", self.url, selection)?
+            }
+        }
+        if let Some(detail) = &self.detail {
+            write!(f, " ({})", detail)?;
+        }
+        for importer in self.chain.iter().rev() {
+            write!(f, "\n  imported by {}", importer)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ImportError {}
+
+impl From<ImportError> for io::Error {
+    fn from(error: ImportError) -> Self {
+        let kind = match error.kind {
+            ImportErrorKind::NotFound | ImportErrorKind::MissingSelection(_) => {
+                io::ErrorKind::NotFound
+            }
+            ImportErrorKind::Decode | ImportErrorKind::Integrity { .. } => {
+                io::ErrorKind::InvalidData
+            }
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, error)
+    }
+}
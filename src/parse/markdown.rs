@@ -1,10 +1,13 @@
 use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+use std::fmt;
 
 /// Extraction of different languages from the Markdown source.
 #[derive(Debug, Default, Eq, PartialEq)]
 pub struct CodeExtraction {
     /// The Rust code to be used in the intermediate binary.
     pub rust: String,
+    /// Rust code retrieved from imports, which runs before the code above.
+    pub imported_rust: String,
     /// The Cargo dependencies that the intermediate binary will depend on.
     pub toml: String,
     /// URLs to import ZAMM READMEs from.
@@ -25,40 +28,185 @@ impl CodeExtraction {
     }
 }
 
-/// Extracts code blocks from the markdown.
-pub fn extract_code(markdown: &str) -> CodeExtraction {
-    // note: go back to commit 158f648 in Yang to retrieve YAML-parsing code, including markdown
-    // quote extraction
-    let mut code = CodeExtraction::default();
-    let mut code_block: Option<String> = None;
+/// A fenced code block, along with where it appears in the document.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Chunk {
+    /// The language of the code block.
+    lang: String,
+    /// The name given to the code block with a `name=` attribute, if any.
+    name: Option<String>,
+    /// Titles of the headings that the code block is nested under, outermost first.
+    headings: Vec<String>,
+    /// The contents of the code block.
+    content: String,
+}
+
+/// The code blocks and headings of a markdown document.
+#[derive(Debug, Default)]
+struct Outline {
+    /// All fenced code blocks in the document, in order.
+    chunks: Vec<Chunk>,
+    /// The full path of titles leading up to each heading in the document.
+    headings: Vec<Vec<String>>,
+}
+
+/// Split the info string of a fenced code block into its language and name. Only info strings of
+/// the form `lang` or `lang name=chunk-name` are recognized, so that blocks marked with something
+/// else (e.g. `rust ignore`) are left out of the extraction.
+fn parse_info(info: &str) -> Option<(String, Option<String>)> {
+    let mut tokens = info.split_whitespace();
+    let lang = tokens.next()?.to_owned();
+    let mut name = None;
+    for token in tokens {
+        match token.strip_prefix("name=") {
+            Some(chunk_name) => name = Some(chunk_name.to_owned()),
+            None => return None,
+        }
+    }
+    Some((lang, name))
+}
+
+fn outline(markdown: &str) -> Outline {
+    let mut outline = Outline::default();
+    let mut heading_stack: Vec<(u32, String)> = vec![];
+    let mut heading: Option<(u32, String)> = None;
+    let mut chunk: Option<Chunk> = None;
+    let titles = |stack: &[(u32, String)]| stack.iter().map(|(_, t)| t.clone()).collect();
     for event in Parser::new(markdown) {
         match event {
-            Event::Start(tag) => {
-                if let Tag::CodeBlock(kind) = tag {
-                    if let CodeBlockKind::Fenced(cow) = kind {
-                        code_block = Some(cow.to_string());
+            Event::Start(Tag::Heading(level)) => heading = Some((level, String::new())),
+            Event::End(Tag::Heading(_)) => {
+                if let Some((level, title)) = heading.take() {
+                    while heading_stack.last().map(|(l, _)| *l >= level) == Some(true) {
+                        heading_stack.pop();
                     }
+                    heading_stack.push((level, title.trim().to_owned()));
+                    outline.headings.push(titles(&heading_stack));
                 }
             }
-            Event::Text(content) => match &code_block {
-                Some(lang) if lang == "rust" => code.rust += &content,
-                Some(lang) if lang == "toml" => code.toml += &content,
-                Some(lang) if lang == "zamm" => code.imports.push(content.trim().to_string()),
-                _ => (),
-            },
-            Event::End(tag) => {
-                if let Tag::CodeBlock(_) = tag {
-                    code_block = None;
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                chunk = parse_info(&info).map(|(lang, name)| Chunk {
+                    lang,
+                    name,
+                    headings: titles(&heading_stack),
+                    content: String::new(),
+                });
+            }
+            Event::End(Tag::CodeBlock(_)) => outline.chunks.extend(chunk.take()),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, title)) = &mut heading {
+                    title.push_str(&text);
+                } else if let Some(c) = &mut chunk {
+                    c.content.push_str(&text);
                 }
             }
             _ => (),
         }
     }
+    outline
+}
 
+fn collect_chunks<'a, I: Iterator<Item = &'a Chunk>>(chunks: I) -> CodeExtraction {
+    let mut code = CodeExtraction::default();
+    for chunk in chunks {
+        match chunk.lang.as_str() {
+            "rust" => code.rust += &chunk.content,
+            "toml" => code.toml += &chunk.content,
+            "zamm" => code.imports.extend(
+                chunk
+                    .content
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(str::to_owned),
+            ),
+            _ => (),
+        }
+    }
     code.trim();
     code
 }
 
+/// Extracts code blocks from the markdown.
+pub fn extract_code(markdown: &str) -> CodeExtraction {
+    // note: go back to commit 158f648 in Yang to retrieve YAML-parsing code, including markdown
+    // quote extraction
+    collect_chunks(outline(markdown).chunks.iter())
+}
+
+/// A part of a markdown document to extract code from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Selector {
+    /// Everything under the heading at this path. The path consists of heading titles separated
+    /// by `/`, and may leave out any number of outer headings.
+    Section(String),
+    /// The code block given this name with a `name=` attribute.
+    Chunk(String),
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::Section(path) => write!(f, "section \"{}\"", path),
+            Selector::Chunk(name) => write!(f, "chunk \"{}\"", name),
+        }
+    }
+}
+
+/// Whether or not the heading titles, starting from the outermost one, end with the given path.
+fn ends_with_path(titles: &[String], path: &[&str]) -> bool {
+    titles.len() >= path.len()
+        && titles[titles.len() - path.len()..]
+            .iter()
+            .zip(path)
+            .all(|(title, component)| title == component)
+}
+
+impl Selector {
+    fn section_path(path: &str) -> Vec<&str> {
+        path.split('/').map(str::trim).collect()
+    }
+
+    /// Whether or not the chunk falls under this selection.
+    fn selects(&self, chunk: &Chunk) -> bool {
+        match self {
+            Selector::Section(path) => {
+                let path = Self::section_path(path);
+                (1..=chunk.headings.len()).any(|i| ends_with_path(&chunk.headings[..i], &path))
+            }
+            Selector::Chunk(name) => chunk.name.as_ref() == Some(name),
+        }
+    }
+
+    /// Whether or not this selection refers to anything in the document at all.
+    fn exists_in(&self, outline: &Outline) -> bool {
+        match self {
+            Selector::Section(path) => {
+                let path = Self::section_path(path);
+                outline.headings.iter().any(|h| ends_with_path(h, &path))
+            }
+            Selector::Chunk(_) => outline.chunks.iter().any(|c| self.selects(c)),
+        }
+    }
+}
+
+/// Extracts code blocks from only the selected parts of the markdown. If there are no selectors,
+/// the whole document is used.
+///
+/// Returns the first selector that doesn't match anything in the document as an error.
+pub fn extract_selected(
+    markdown: &str,
+    selectors: &[Selector],
+) -> Result<CodeExtraction, Selector> {
+    let outline = outline(markdown);
+    if let Some(missing) = selectors.iter().find(|s| !s.exists_in(&outline)) {
+        return Err(missing.clone());
+    }
+    Ok(collect_chunks(outline.chunks.iter().filter(|chunk| {
+        selectors.is_empty() || selectors.iter().any(|s| s.selects(chunk))
+    })))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -266,7 +414,128 @@ mod tests {
                     "https://api.zamm.dev".to_owned(),
                     "http://www.asdf.com".to_owned()
                 ],
+                ..CodeExtraction::default()
             }
         );
     }
+
+    #[test]
+    fn test_extraction_multiline_imports() {
+        assert_eq!(
+            extract_code(indoc! {"
+            ```zamm
+            https://api.zamm.dev/yin.md
+
+            ../yang.md
+            ```
+        "}),
+            CodeExtraction {
+                imports: vec![
+                    "https://api.zamm.dev/yin.md".to_owned(),
+                    "../yang.md".to_owned()
+                ],
+                ..CodeExtraction::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_extraction_info_attributes() {
+        assert_eq!(
+            extract_code(indoc! {"
+            ```rust name=setup
+            let x = 5;
+            ```
+
+            ```rust ignore
+            let y = 6;
+            ```
+        "}),
+            CodeExtraction {
+                rust: "let x = 5;\n".to_owned(),
+                ..CodeExtraction::default()
+            }
+        );
+    }
+
+    const SECTIONED: &str = indoc! {"
+        # Book
+
+        ```rust
+        let intro = 0;
+        ```
+
+        ## Helpers
+
+        ```rust
+        let helper = 1;
+        ```
+
+        ### Extra `helpers`
+
+        ```rust name=extra
+        let extra = 2;
+        ```
+
+        ## Main
+
+        ```rust
+        let main = 3;
+        ```
+    "};
+
+    #[test]
+    fn test_selected_nothing() {
+        assert_eq!(
+            extract_selected(SECTIONED, &[]),
+            Ok(extract_code(SECTIONED))
+        );
+    }
+
+    #[test]
+    fn test_selected_section() {
+        assert_eq!(
+            extract_selected(SECTIONED, &[Selector::Section("Helpers".to_owned())])
+                .unwrap()
+                .rust,
+            "let helper = 1;\nlet extra = 2;\n"
+        );
+        assert_eq!(
+            extract_selected(
+                SECTIONED,
+                &[Selector::Section("Book/Helpers/Extra helpers".to_owned())]
+            )
+            .unwrap()
+            .rust,
+            "let extra = 2;\n"
+        );
+    }
+
+    #[test]
+    fn test_selected_chunk_and_section() {
+        assert_eq!(
+            extract_selected(
+                SECTIONED,
+                &[
+                    Selector::Chunk("extra".to_owned()),
+                    Selector::Section("Main".to_owned())
+                ]
+            )
+            .unwrap()
+            .rust,
+            "let extra = 2;\nlet main = 3;\n"
+        );
+    }
+
+    #[test]
+    fn test_selected_missing() {
+        assert_eq!(
+            extract_selected(SECTIONED, &[Selector::Section("Book/Main/Nope".to_owned())]),
+            Err(Selector::Section("Book/Main/Nope".to_owned()))
+        );
+        assert_eq!(
+            extract_selected(SECTIONED, &[Selector::Chunk("nope".to_owned())]),
+            Err(Selector::Chunk("nope".to_owned()))
+        );
+    }
 }
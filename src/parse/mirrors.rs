@@ -0,0 +1,113 @@
+use crate::build_script;
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+
+/// Environment variable listing import mirrors as `prefix=replacement` pairs separated by `;`.
+pub const ZAMM_MIRRORS_VAR: &str = "ZAMM_IMPORT_MIRRORS";
+
+/// Table of import location prefixes to rewrite before retrieving imports. A prefix can be
+/// rewritten to another URL prefix, or to a local directory.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Mirrors {
+    /// Pairs of prefixes to look for, and what to replace them with.
+    pub rewrites: Vec<(String, String)>,
+}
+
+impl Mirrors {
+    /// Parse a mirror table from `prefix=replacement` pairs separated by `;`.
+    pub fn parse(table: &str) -> Result<Self> {
+        let mut mirrors = Self::default();
+        for entry in table.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut parts = entry.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(prefix), Some(replacement)) if !prefix.trim().is_empty() => {
+                    mirrors.add(prefix.trim(), replacement.trim())
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "Mirror entry \"{}\" should be of the form prefix=replacement",
+                            entry
+                        ),
+                    ))
+                }
+            }
+        }
+        Ok(mirrors)
+    }
+
+    /// Read the mirror table from the `ZAMM_IMPORT_MIRRORS` environment variable, if it is set.
+    pub fn from_env() -> Result<Self> {
+        build_script::rerun_if_env_changed(ZAMM_MIRRORS_VAR);
+        match env::var(ZAMM_MIRRORS_VAR) {
+            Ok(table) => Self::parse(&table),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Add a rewrite rule to the table.
+    pub fn add(&mut self, prefix: &str, replacement: &str) {
+        self.rewrites
+            .push((prefix.to_owned(), replacement.to_owned()));
+    }
+
+    /// Where the given import location should actually be retrieved from. When multiple prefixes
+    /// match, the longest one wins. Replacements starting with `file://` refer to local paths.
+    pub fn resolve(&self, location: &str) -> String {
+        let rewrite = self
+            .rewrites
+            .iter()
+            .filter(|(prefix, _)| location.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len());
+        let resolved = match rewrite {
+            Some((prefix, replacement)) => format!("{}{}", replacement, &location[prefix.len()..]),
+            None => location.to_owned(),
+        };
+        match resolved.strip_prefix("file://") {
+            Some(path) => path.to_owned(),
+            None => resolved,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(Mirrors::parse(" ").unwrap(), Mirrors::default());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Mirrors::parse("https://api.zamm.dev/").is_err());
+    }
+
+    #[test]
+    fn test_resolve_unmatched() {
+        let mirrors = Mirrors::parse("https://api.zamm.dev/=https://mirror.corp/zamm/").unwrap();
+        assert_eq!(
+            mirrors.resolve("https://example.com/yin.md"),
+            "https://example.com/yin.md"
+        );
+    }
+
+    #[test]
+    fn test_resolve_longest_prefix() {
+        let mirrors = Mirrors::parse(
+            "https://api.zamm.dev/=https://mirror.corp/zamm/; \
+            https://api.zamm.dev/v1/books/zamm/yin/=file:///srv/books/yin/",
+        )
+        .unwrap();
+        assert_eq!(
+            mirrors.resolve("https://api.zamm.dev/v1/books/zamm/yang/0.1.0/yin.md"),
+            "https://mirror.corp/zamm/v1/books/zamm/yang/0.1.0/yin.md"
+        );
+        assert_eq!(
+            mirrors.resolve("https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md"),
+            "/srv/books/yin/0.1.0/yin.md"
+        );
+    }
+}
@@ -1,15 +1,44 @@
+/// Credentials for downloading imports from private servers.
+mod credentials;
+/// Settings at the start of input files.
+mod front_matter;
 /// Grabs imported data.
 mod handle_imports;
+/// Local copies of downloaded imports.
+mod import_cache;
+/// Errors encountered while retrieving imports.
+mod import_error;
 /// Literate programming support - extracts relevant code from Markdown file.
 pub mod markdown;
+/// Rewriting import locations to mirrors.
+mod mirrors;
+/// Merging override files into the input file.
+mod overrides;
 
-use handle_imports::retrieve_imports;
-pub use markdown::{extract_code, CodeExtraction};
+use crate::build_script;
+use crate::timings::{time, Phase};
+pub use credentials::{
+    redact_url, Credential, Credentials, BASIC_AUTH_VAR_PREFIX, TOKEN_VAR_PREFIX,
+    ZAMM_CREDENTIALS_VAR,
+};
+pub use front_matter::{parse_front_matter, FrontMatter, FRONT_MATTER_DELIMITER};
+pub use handle_imports::{
+    normalize, relative_path, resolve_import_tree, ImportNode, ImportOrigin, ImportSpec,
+    ZAMM_OFFLINE_VAR,
+};
+use handle_imports::{resolve_specs, retrieve_imports};
+pub use import_cache::ImportCache;
+pub use import_error::{ImportError, ImportErrorKind};
+pub use markdown::{extract_code, extract_selected, CodeExtraction, Selector};
+pub use mirrors::{Mirrors, ZAMM_MIRRORS_VAR};
+pub use overrides::{
+    apply_override, rebase_import, OverrideChange, OverrideLayer, REMOVE_IMPORT_PREFIX,
+};
 use path_abs::{PathAbs, PathInfo};
 use std::env;
 use std::fs::read_to_string;
 use std::io::{Error, ErrorKind};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// All supported input filename extensions.
 pub const SUPPORTED_EXTENSIONS: &[&str] = &["md"];
@@ -17,24 +46,80 @@ pub const SUPPORTED_EXTENSIONS: &[&str] = &["md"];
 /// Filename for ZAMM override file.
 pub const ZAMM_OVERRIDE_NAME: &str = "zamm_override.md";
 
+/// Environment variable listing extra override files to apply, separated the same way as `PATH`.
+pub const ZAMM_OVERRIDE_VAR: &str = "ZAMM_OVERRIDE";
+
+/// Settings for how input files and their imports get parsed.
+#[derive(Debug, Clone, Default)]
+pub struct ParseConfig {
+    /// Rewrites to apply to import locations before retrieving them.
+    pub mirrors: Mirrors,
+    /// Credentials to authenticate import downloads with.
+    pub credentials: Credentials,
+    /// Where to keep copies of downloaded imports, if anywhere.
+    pub cache: Option<ImportCache>,
+    /// Whether or not to keep messages off of stdout, so that it can be used for other output.
+    pub quiet: bool,
+    /// Override files to apply on top of the user and project override files, in order.
+    pub overrides: Vec<PathBuf>,
+    /// Directory containing the project override file. The current working directory is used if
+    /// this is empty.
+    pub project_root: PathBuf,
+    /// Directory containing the user-wide override file, if any.
+    pub user_config_dir: Option<PathBuf>,
+}
+
+impl ParseConfig {
+    /// Parse settings as specified by environment variables and the user's credentials file.
+    pub fn from_env() -> Result<Self, Error> {
+        build_script::rerun_if_env_changed(ZAMM_OVERRIDE_VAR);
+        Ok(Self {
+            mirrors: Mirrors::from_env()?,
+            credentials: Credentials::load()?,
+            cache: ImportCache::user_cache(),
+            quiet: false,
+            overrides: env::var_os(ZAMM_OVERRIDE_VAR)
+                .map(|paths| env::split_paths(&paths).collect())
+                .unwrap_or_default(),
+            project_root: PathBuf::new(),
+            user_config_dir: dirs::config_dir().map(|d| d.join("zamm")),
+        })
+    }
+}
+
 /// Parse output, including the original markdown text.
 pub struct ParseOutput {
     /// The original filename.
     pub filename: String,
+    /// Absolute path to the original file.
+    pub path: PathBuf,
     /// The original markdown text.
     pub markdown: String,
     /// Code extractions from the original markdown.
     pub extractions: CodeExtraction,
+    /// Settings from the front matter of the original markdown.
+    pub front_matter: FrontMatter,
 }
 
-/// Find the right input file.
-pub fn find_file(specified_file: Option<&str>) -> Result<PathAbs, Error> {
+/// Find the right input file in the given project directory.
+pub fn find_file(project_root: &Path, specified_file: Option<&str>) -> Result<PathAbs, Error> {
+    let path = locate_file(project_root, specified_file)?;
+    let path_str = path.as_path().to_str().unwrap();
+    match specified_file {
+        Some(_) => println!("Using specified input file at {}", path_str),
+        None => println!("Using default input file at {}", path_str),
+    }
+    Ok(path)
+}
+
+/// Find the right input file in the given project directory without printing anything out.
+/// Specified files are taken to be relative to the project directory.
+pub fn locate_file(project_root: &Path, specified_file: Option<&str>) -> Result<PathAbs, Error> {
     match specified_file {
         Some(filename) => {
-            let path = PathAbs::new(Path::new(&filename))?;
+            let path = PathAbs::new(project_root.join(filename))?;
             let path_str = path.as_path().to_str().unwrap();
             if path.exists() {
-                println!("Using specified input file at {}", path_str);
                 Ok(path)
             } else {
                 Err(Error::new(
@@ -45,74 +130,97 @@ pub fn find_file(specified_file: Option<&str>) -> Result<PathAbs, Error> {
         }
         None => {
             for extension in SUPPORTED_EXTENSIONS {
-                let path = PathAbs::new(Path::new(format!("yin.{}", extension).as_str()))?;
+                let path = PathAbs::new(project_root.join(format!("yin.{}", extension)))?;
                 if path.exists() {
-                    println!(
-                        "Using default input file at {}",
-                        path.as_path().to_str().unwrap()
-                    );
                     return Ok(path);
                 }
             }
-            let current_dir = env::current_dir()?;
-            let current_dir_path = current_dir.to_str().unwrap();
+            let project_dir = PathAbs::new(project_root)?;
             Err(Error::new(
                 ErrorKind::NotFound,
                 format!(
-                    "No input file was specified, and no default inputs were found in the current \
+                    "No input file was specified, and no default inputs were found in the project \
                     directory of {}",
-                    current_dir_path
+                    project_dir.as_path().to_str().unwrap()
                 ),
             ))
         }
     }
 }
 
-fn retrieve_override() -> Result<Option<String>, Error> {
-    let override_path = PathAbs::new(Path::new(ZAMM_OVERRIDE_NAME))?;
-    if override_path.exists() {
-        let override_content = read_to_string(&override_path)?;
-        Ok(Some(override_content))
-    } else {
-        Ok(None)
+/// All override files to apply, in the order they should be applied: the user-wide override file,
+/// then the project override file, and finally any others that were explicitly asked for. The
+/// first two are skipped if they don't exist, but the rest must exist. Build scripts are rerun
+/// whenever any of them change, including when the first two get created.
+fn override_files(parse_cfg: &ParseConfig) -> Result<Vec<PathAbs>, Error> {
+    let mut files = vec![];
+    let defaults = parse_cfg
+        .user_config_dir
+        .iter()
+        .chain(Some(&parse_cfg.project_root))
+        .map(|dir| dir.join(ZAMM_OVERRIDE_NAME));
+    for path in defaults {
+        build_script::rerun_if_changed(&path);
+        let path = PathAbs::new(path)?;
+        if path.exists() {
+            files.push(path);
+        }
     }
+    for path in &parse_cfg.overrides {
+        build_script::rerun_if_changed(path);
+        let path = PathAbs::new(path)?;
+        if !path.exists() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Override file {} does not exist",
+                    path.as_os_str().to_str().unwrap()
+                ),
+            ));
+        }
+        files.push(path);
+    }
+    Ok(files)
 }
 
-/// Parse the given input file.
-pub fn parse_input(found_input: PathAbs) -> Result<ParseOutput, Error> {
-    println!(
-        "cargo:rerun-if-changed={}",
-        found_input.as_os_str().to_str().unwrap()
-    );
-    let contents = read_to_string(&found_input)?;
+/// Read the given input file and apply overrides to it, without retrieving any imports yet.
+///
+/// Returns the original contents of the input file alongside the code extracted from it, and the
+/// changes that each override file made.
+fn read_input_with_changes(
+    found_input: &PathAbs,
+    parse_cfg: &ParseConfig,
+) -> Result<(String, CodeExtraction, Vec<OverrideLayer>), Error> {
+    build_script::rerun_if_changed(found_input);
+    let contents = time(Phase::Parse, || read_to_string(found_input))?;
     let extension = found_input
         .extension()
         .map(|e| e.to_str().unwrap())
         .unwrap_or("");
     match extension {
         "md" => {
-            let mut initial_extraction = extract_code(&contents);
-            let override_content: String = retrieve_override()?.unwrap_or_default();
-            let override_extraction = extract_code(&override_content);
-
-            initial_extraction.rust += &override_extraction.rust;
-            if !override_extraction.imports.is_empty() {
-                initial_extraction.imports = override_extraction.imports;
-            }
-            if !override_extraction.toml.is_empty() {
-                initial_extraction.toml = override_extraction.toml;
-            }
-
-            Ok(ParseOutput {
-                filename: found_input
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_owned(),
-                markdown: contents,
-                extractions: retrieve_imports(&initial_extraction)?,
-            })
+            let mut initial_extraction = time(Phase::Parse, || extract_code(&contents));
+            let layers = time(Phase::Overrides, || -> Result<_, Error> {
+                let mut layers = vec![];
+                let input_dir = found_input.as_path().parent().unwrap();
+                for override_path in override_files(parse_cfg)? {
+                    let path_str = override_path.as_os_str().to_str().unwrap();
+                    let mut override_extraction = extract_code(&read_to_string(&override_path)?);
+                    // imports get resolved relative to the input file once they're merged into it
+                    let override_dir = override_path.as_path().parent().unwrap();
+                    for import in &mut override_extraction.imports {
+                        *import = rebase_import(import, override_dir, input_dir);
+                    }
+                    let changes =
+                        apply_override(&mut initial_extraction, &override_extraction, path_str)?;
+                    layers.push(OverrideLayer {
+                        path: override_path.as_path().to_path_buf(),
+                        changes,
+                    });
+                }
+                Ok(layers)
+            })?;
+            Ok((contents, initial_extraction, layers))
         }
         _ => Err(Error::new(
             ErrorKind::NotFound,
@@ -124,3 +232,202 @@ pub fn parse_input(found_input: PathAbs) -> Result<ParseOutput, Error> {
         )),
     }
 }
+
+/// Read the given input file and apply overrides to it, without retrieving any imports yet.
+///
+/// Returns the original contents of the input file alongside the code extracted from it.
+fn read_input(
+    found_input: &PathAbs,
+    parse_cfg: &ParseConfig,
+) -> Result<(String, CodeExtraction), Error> {
+    read_input_with_changes(found_input, parse_cfg)
+        .map(|(contents, extraction, _)| (contents, extraction))
+}
+
+/// List everything that each override file changes about the input file, in the order that the
+/// override files are applied.
+pub fn explain_overrides(
+    found_input: &PathAbs,
+    parse_cfg: &ParseConfig,
+) -> Result<Vec<OverrideLayer>, Error> {
+    read_input_with_changes(found_input, parse_cfg).map(|(_, _, layers)| layers)
+}
+
+/// Parse the given input file.
+pub fn parse_input(found_input: PathAbs, parse_cfg: &ParseConfig) -> Result<ParseOutput, Error> {
+    let (contents, initial_extraction) = read_input(&found_input, parse_cfg)?;
+    Ok(ParseOutput {
+        path: found_input.as_path().to_path_buf(),
+        filename: found_input
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned(),
+        extractions: time(Phase::Imports, || {
+            retrieve_imports(
+                &initial_extraction,
+                found_input.as_os_str().to_str().unwrap(),
+                parse_cfg,
+            )
+        })?,
+        front_matter: time(Phase::Parse, || parse_front_matter(&contents))?,
+        markdown: contents,
+    })
+}
+
+/// List the imports of the given input file, alongside the final locations they will be
+/// retrieved from after mirrors are applied.
+pub fn resolve_import_locations(
+    found_input: PathAbs,
+    parse_cfg: &ParseConfig,
+) -> Result<Vec<(String, String)>, Error> {
+    let (_, extraction) = read_input(&found_input, parse_cfg)?;
+    let importer = found_input.as_os_str().to_str().unwrap();
+    Ok(resolve_specs(&extraction, importer, parse_cfg)
+        .into_iter()
+        .map(|(written, spec)| (redact_url(&written), redact_url(&spec.location)))
+        .collect())
+}
+
+/// Retrieve all imports of the given input file, including the imports of those imports.
+pub fn resolve_input_imports(
+    found_input: &PathAbs,
+    parse_cfg: &ParseConfig,
+) -> Result<Vec<ImportNode>, Error> {
+    let (_, extraction) = read_input(found_input, parse_cfg)?;
+    resolve_import_tree(
+        &extraction,
+        found_input.as_os_str().to_str().unwrap(),
+        parse_cfg,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn temp_file(dir: &TempDir, name: &str, contents: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_locate_file_in_project_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        assert_eq!(
+            locate_file(root, None).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        fs::write(root.join("yin.md"), "").unwrap();
+        fs::write(root.join("other.md"), "").unwrap();
+        assert_eq!(
+            locate_file(root, None).unwrap().as_path(),
+            root.join("yin.md")
+        );
+        assert_eq!(
+            locate_file(root, Some("other.md")).unwrap().as_path(),
+            root.join("other.md")
+        );
+    }
+
+    #[test]
+    fn test_overrides_layered_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = temp_file(&dir, "layered.md", "```toml\nzamm_yang = \"0.1.5\"\n```\n");
+        fs::create_dir(dir.path().join("user")).unwrap();
+        let user = temp_file(
+            &dir,
+            "user/zamm_override.md",
+            "```toml\nzamm_yin = \"0.1.0\"\n```\n",
+        );
+        let project = temp_file(
+            &dir,
+            "zamm_override.md",
+            "```toml\nzamm_yang = { path = \"yang\" }\n```\n",
+        );
+        let first = temp_file(
+            &dir,
+            "layered-first.md",
+            "```toml\nzamm_yang = { path = \"../yang\" }\n```\n",
+        );
+        let second = temp_file(
+            &dir,
+            "layered-second.md",
+            "```toml\nzamm_yang = { path = \"../../yang\" }\n```\n",
+        );
+        let parse_cfg = ParseConfig {
+            quiet: true,
+            overrides: vec![first.clone(), second.clone()],
+            project_root: dir.path().to_owned(),
+            user_config_dir: Some(dir.path().join("user")),
+            ..ParseConfig::default()
+        };
+        let (_, extraction, layers) =
+            read_input_with_changes(&PathAbs::new(&input).unwrap(), &parse_cfg).unwrap();
+        assert_eq!(
+            extraction.toml,
+            "zamm_yang = { path = \"../../yang\", version = \"0.1.5\" }\nzamm_yin = \"0.1.0\"\n"
+        );
+        let layer_paths: Vec<&Path> = layers.iter().map(|l| l.path.as_path()).collect();
+        assert_eq!(
+            layer_paths,
+            vec![
+                user.as_path(),
+                project.as_path(),
+                first.as_path(),
+                second.as_path()
+            ]
+        );
+        assert_eq!(
+            layers[3].changes,
+            vec![OverrideChange::TomlSet {
+                key: "zamm_yang.path".to_owned(),
+                from: Some("\"../yang\"".to_owned()),
+                to: "\"../../yang\"".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_override_imports_relative_to_override_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        let input = temp_file(&dir, "docs/yin.md", "```zamm\n../yang/yang.md\n```\n");
+        fs::create_dir(dir.path().join("user")).unwrap();
+        temp_file(
+            &dir,
+            "user/zamm_override.md",
+            "```zamm\ncheckouts/yin.md#name=zamm/yin\n!../yang/yang.md\n```\n",
+        );
+        let parse_cfg = ParseConfig {
+            quiet: true,
+            project_root: dir.path().to_owned(),
+            user_config_dir: Some(dir.path().join("user")),
+            ..ParseConfig::default()
+        };
+        let (_, extraction, _) =
+            read_input_with_changes(&PathAbs::new(&input).unwrap(), &parse_cfg).unwrap();
+        // removals refer to what the input file says, so they're left as written
+        assert_eq!(
+            extraction.imports,
+            vec!["../user/checkouts/yin.md#name=zamm/yin".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_missing_explicit_override() {
+        let parse_cfg = ParseConfig {
+            overrides: vec![PathBuf::from("/nonexistent/zamm_override.md")],
+            ..ParseConfig::default()
+        };
+        assert_eq!(
+            override_files(&parse_cfg).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
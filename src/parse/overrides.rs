@@ -0,0 +1,595 @@
+use super::{normalize, relative_path, CodeExtraction, ImportSpec};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use toml::value::Table;
+use toml::Value;
+
+/// Prefix for import entries in override files that remove an import instead of adding one.
+pub const REMOVE_IMPORT_PREFIX: char = '!';
+
+/// A single change that an override file made to the input file's code.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum OverrideChange {
+    /// An import that wasn't there before was added.
+    ImportAdded(String),
+    /// An existing import was swapped out for another one with the same name.
+    ImportReplaced {
+        /// The import as it was originally.
+        from: String,
+        /// The import that took its place.
+        to: String,
+    },
+    /// An existing import was removed.
+    ImportRemoved(String),
+    /// A TOML key was set to a new value.
+    TomlSet {
+        /// Dotted path to the key.
+        key: String,
+        /// The value the key had originally, if it had one.
+        from: Option<String>,
+        /// The value that the key now has.
+        to: String,
+    },
+    /// A TOML key was removed by setting it to `false`.
+    TomlRemoved {
+        /// Dotted path to the key.
+        key: String,
+        /// The value the key had originally.
+        from: String,
+    },
+    /// Rust code was appended to the end of the input file's code.
+    RustAppended {
+        /// How many lines of code were added.
+        lines: usize,
+    },
+}
+
+impl fmt::Display for OverrideChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverrideChange::ImportAdded(import) => write!(f, "added import {}", import),
+            OverrideChange::ImportReplaced { from, to } => {
+                write!(f, "replaced import {} with {}", from, to)
+            }
+            OverrideChange::ImportRemoved(import) => write!(f, "removed import {}", import),
+            OverrideChange::TomlSet {
+                key,
+                from: Some(from),
+                to,
+            } => write!(f, "changed {} from {} to {}", key, from, to),
+            OverrideChange::TomlSet {
+                key,
+                from: None,
+                to,
+            } => write!(f, "set {} to {}", key, to),
+            OverrideChange::TomlRemoved { key, from } => {
+                write!(f, "removed {} (was {})", key, from)
+            }
+            OverrideChange::RustAppended { lines } => {
+                write!(f, "appended {} lines of Rust code", lines)
+            }
+        }
+    }
+}
+
+/// The changes made by a single override file.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OverrideLayer {
+    /// The override file.
+    pub path: PathBuf,
+    /// Everything that the override file changed, on top of the override files before it.
+    pub changes: Vec<OverrideChange>,
+}
+
+/// Format a TOML value on a single line, so that it can go inside the `[dependencies]` table of
+/// the intermediate build.
+fn inline_toml(value: &Value) -> String {
+    match value {
+        Value::Table(table) => {
+            let entries: Vec<String> = table
+                .iter()
+                .map(|(k, v)| format!("{} = {}", toml_key(k), inline_toml(v)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        Value::Array(array) => {
+            let items: Vec<String> = array.iter().map(inline_toml).collect();
+            format!("[{}]", items.join(", "))
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn toml_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        key.to_owned()
+    } else {
+        Value::String(key.to_owned()).to_string()
+    }
+}
+
+fn parse_toml(toml: &str, description: &str) -> Result<Table> {
+    match toml.parse::<Value>() {
+        Ok(Value::Table(table)) => Ok(table),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("The TOML code in {} is not valid", description),
+        )),
+    }
+}
+
+/// Split literate TOML into the dependencies at the top, which go inside the `[dependencies]` table
+/// of the intermediate build, and any tables that follow them, such as `[patch.crates-io]`.
+/// Comments right above the first table header stay with the tables.
+fn split_toml(toml: &str) -> (&str, &str) {
+    let mut offset = 0;
+    let mut tables_start = 0;
+    for line in toml.split_inclusive('\n') {
+        let line_start = line.trim_start();
+        // a line that starts with a bracket could also be part of a multi-line array
+        if line_start.starts_with('[') && toml[..offset].parse::<Value>().is_ok() {
+            return toml.split_at(tables_start);
+        }
+        offset += line.len();
+        if !line_start.is_empty() && !line_start.starts_with('#') {
+            tables_start = offset;
+        }
+    }
+    (toml, "")
+}
+
+/// Merge the TOML code of the overlay into that of the base. Returns `None` if the overlay has
+/// nothing to merge, so that the base can be left exactly as it was written.
+fn merge_toml_code(
+    base: &str,
+    overlay: &str,
+    description: &str,
+    changes: &mut Vec<OverrideChange>,
+) -> Result<Option<Table>> {
+    if overlay.trim().is_empty() {
+        return Ok(None);
+    }
+    let mut table = parse_toml(base, "the input file")?;
+    merge_toml(&mut table, parse_toml(overlay, description)?, "", changes);
+    Ok(Some(table))
+}
+
+/// Merge the overlay into the base TOML key by key. Keys that aren't booleans can be removed by
+/// setting them to `false`.
+fn merge_toml(base: &mut Table, overlay: Table, prefix: &str, changes: &mut Vec<OverrideChange>) {
+    for (key, value) in overlay {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        let existing = base.remove(&key);
+        match (existing, value) {
+            (Some(existing), Value::Boolean(false)) if !existing.is_bool() => {
+                changes.push(OverrideChange::TomlRemoved {
+                    key: path,
+                    from: inline_toml(&existing),
+                });
+            }
+            (Some(Value::Table(mut table)), Value::Table(overlay_table)) => {
+                merge_toml(&mut table, overlay_table, &path, changes);
+                base.insert(key, Value::Table(table));
+            }
+            (Some(Value::String(version)), Value::Table(overlay_table)) => {
+                // a dependency given as just a version string is shorthand for a table
+                let mut table = Table::new();
+                table.insert("version".to_owned(), Value::String(version));
+                merge_toml(&mut table, overlay_table, &path, changes);
+                base.insert(key, Value::Table(table));
+            }
+            (existing, value) => {
+                if existing.as_ref() != Some(&value) {
+                    changes.push(OverrideChange::TomlSet {
+                        key: path,
+                        from: existing.as_ref().map(inline_toml),
+                        to: inline_toml(&value),
+                    });
+                }
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Rewrite an import entry written in a file in `from_dir`, so that it points to the same file when
+/// written in a file in `to_dir` instead. Network imports, absolute paths, and removals, which can
+/// be by name, are left alone.
+pub fn rebase_import(entry: &str, from_dir: &Path, to_dir: &Path) -> String {
+    let mut spec = ImportSpec::parse(entry);
+    if from_dir == to_dir
+        || entry.trim().is_empty()
+        || entry.trim().starts_with(REMOVE_IMPORT_PREFIX)
+        || spec.is_network()
+        || Path::new(&spec.location).is_absolute()
+    {
+        return entry.to_owned();
+    }
+    let target = normalize(&from_dir.join(&spec.location));
+    spec.location = relative_path(&normalize(to_dir), &target)
+        .to_str()
+        .unwrap()
+        .to_owned();
+    spec.to_string()
+}
+
+/// Find the network import that an unnamed local import replaces, for overrides written before
+/// imports could be named. Back then, swapping a book for a local checkout of it meant listing only
+/// the checkout, so this works as long as there's no doubt about which book the checkout is for.
+fn legacy_replacement(
+    base: &[String],
+    overridden: &[String],
+    entry: &str,
+    description: &str,
+) -> Result<Option<usize>> {
+    let candidates: Vec<usize> = base
+        .iter()
+        .enumerate()
+        .filter(|(_, existing)| {
+            let spec = ImportSpec::parse(existing);
+            spec.is_network() && !overridden.contains(&spec.identity())
+        })
+        .map(|(i, _)| i)
+        .collect();
+    match candidates.as_slice() {
+        [] => Ok(None),
+        [only] => Ok(Some(*only)),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Import {} in {} could replace any of several imports of the input file. Add \
+                the name of the import it replaces, as in {}#name=<org>/<book>, or give it a new \
+                name to import it alongside them.",
+                entry, description, entry
+            ),
+        )),
+    }
+}
+
+/// Merge the overlay imports into the base imports. Imports with the same name (see
+/// `ImportSpec::identity`) get replaced, and entries starting with `!` remove the import with that
+/// name or location. An unnamed local import that matches nothing replaces the only network
+/// import that isn't otherwise overridden, as overrides used to do.
+fn merge_imports(
+    base: &mut Vec<String>,
+    overlay: &[String],
+    description: &str,
+    changes: &mut Vec<OverrideChange>,
+) -> Result<()> {
+    let entries: Vec<&str> = overlay
+        .iter()
+        .map(|e| e.trim())
+        .filter(|e| !e.is_empty())
+        .collect();
+    let overridden: Vec<String> = entries
+        .iter()
+        .map(|e| ImportSpec::parse(e.trim_start_matches(REMOVE_IMPORT_PREFIX)).identity())
+        .collect();
+    for entry in entries {
+        if let Some(removal) = entry.strip_prefix(REMOVE_IMPORT_PREFIX) {
+            let target = ImportSpec::parse(removal);
+            let (removed, kept): (Vec<String>, Vec<String>) =
+                base.drain(..).partition(|existing| {
+                    let spec = ImportSpec::parse(existing);
+                    spec.identity() == target.identity() || spec.location == target.location
+                });
+            *base = kept;
+            changes.extend(removed.into_iter().map(OverrideChange::ImportRemoved));
+            continue;
+        }
+
+        let spec = ImportSpec::parse(entry);
+        let identity = spec.identity();
+        let mut matching = base
+            .iter()
+            .position(|existing| ImportSpec::parse(existing).identity() == identity);
+        if matching.is_none() && spec.name.is_none() && !spec.is_network() {
+            matching = legacy_replacement(base, &overridden, entry, description)?;
+        }
+        match matching.map(|i| &mut base[i]) {
+            Some(existing) if existing == entry => (),
+            Some(existing) => {
+                changes.push(OverrideChange::ImportReplaced {
+                    from: existing.clone(),
+                    to: entry.to_owned(),
+                });
+                *existing = entry.to_owned();
+            }
+            None => {
+                changes.push(OverrideChange::ImportAdded(entry.to_owned()));
+                base.push(entry.to_owned());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Apply the code from an override file on top of the base code. Rust code gets appended, TOML
+/// gets merged key by key, and imports get replaced or removed by name. Dependencies and any
+/// tables after them are merged separately, and whichever of the two the override file doesn't
+/// touch is left as it was written.
+///
+/// Returns a list of everything that the override changed.
+pub fn apply_override(
+    base: &mut CodeExtraction,
+    overlay: &CodeExtraction,
+    description: &str,
+) -> Result<Vec<OverrideChange>> {
+    let mut changes = vec![];
+    merge_imports(
+        &mut base.imports,
+        &overlay.imports,
+        description,
+        &mut changes,
+    )?;
+
+    if !overlay.toml.trim().is_empty() {
+        let (base_dependencies, base_tables) = split_toml(&base.toml);
+        let (overlay_dependencies, overlay_tables) = split_toml(&overlay.toml);
+        let mut toml = match merge_toml_code(
+            base_dependencies,
+            overlay_dependencies,
+            description,
+            &mut changes,
+        )? {
+            Some(dependencies) => dependencies
+                .iter()
+                .map(|(k, v)| format!("{} = {}\n", toml_key(k), inline_toml(v)))
+                .collect(),
+            None => base_dependencies.to_owned(),
+        };
+        let tables = match merge_toml_code(base_tables, overlay_tables, description, &mut changes)?
+        {
+            Some(tables) => Value::Table(tables).to_string(),
+            None => base_tables.to_owned(),
+        };
+        if !tables.is_empty() && !toml.is_empty() && !toml.ends_with('\n') {
+            toml.push('\n');
+        }
+        base.toml = toml + &tables;
+    }
+
+    if !overlay.rust.trim().is_empty() {
+        if !base.rust.is_empty() && !base.rust.ends_with('\n') {
+            base.rust.push('\n');
+        }
+        base.rust += &overlay.rust;
+        changes.push(OverrideChange::RustAppended {
+            lines: overlay.rust.trim().lines().count(),
+        });
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    fn extraction(toml: &str, imports: &[&str]) -> CodeExtraction {
+        CodeExtraction {
+            toml: toml.to_owned(),
+            imports: imports.iter().map(|i| (*i).to_owned()).collect(),
+            ..CodeExtraction::default()
+        }
+    }
+
+    #[test]
+    fn test_toml_merged_per_key() {
+        let mut base = extraction(
+            indoc! {r#"
+                zamm_yang = "0.1.5"
+                zamm_yin = { version = "0.1.0", features = ["full"] }
+                itertools = "0.9"
+            "#},
+            &[],
+        );
+        let overlay = extraction(
+            indoc! {r#"
+                zamm_yang = { path = "../yang" }
+                zamm_yin = { default-features = false }
+                itertools = false
+            "#},
+            &[],
+        );
+        let changes = apply_override(&mut base, &overlay, "zamm_override.md").unwrap();
+        assert_eq!(
+            base.toml,
+            indoc! {r#"
+                zamm_yang = { path = "../yang", version = "0.1.5" }
+                zamm_yin = { default-features = false, features = ["full"], version = "0.1.0" }
+            "#}
+        );
+        assert_eq!(
+            changes,
+            vec![
+                OverrideChange::TomlRemoved {
+                    key: "itertools".to_owned(),
+                    from: "\"0.9\"".to_owned(),
+                },
+                OverrideChange::TomlSet {
+                    key: "zamm_yang.path".to_owned(),
+                    from: None,
+                    to: "\"../yang\"".to_owned(),
+                },
+                OverrideChange::TomlSet {
+                    key: "zamm_yin.default-features".to_owned(),
+                    from: None,
+                    to: "false".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_toml_untouched_without_override() {
+        let mut base = extraction("# a comment\nzamm_yang = \"0.1.5\"", &[]);
+        apply_override(&mut base, &extraction("", &[]), "zamm_override.md").unwrap();
+        assert_eq!(base.toml, "# a comment\nzamm_yang = \"0.1.5\"");
+    }
+
+    #[test]
+    fn test_toml_tables_kept_apart_from_dependencies() {
+        let mut base = extraction(
+            indoc! {r#"
+                zamm_yang = "0.1.5"
+                itertools = "0.9"
+
+                # use the local checkout of Yin
+                [patch.crates-io]
+                zamm_yin = { path = "../yin" }
+            "#},
+            &[],
+        );
+        let overlay = extraction("zamm_yang = { path = \"../yang\" }", &[]);
+        apply_override(&mut base, &overlay, "zamm_override.md").unwrap();
+        assert_eq!(
+            base.toml,
+            indoc! {r#"
+                itertools = "0.9"
+                zamm_yang = { path = "../yang", version = "0.1.5" }
+
+                # use the local checkout of Yin
+                [patch.crates-io]
+                zamm_yin = { path = "../yin" }
+            "#}
+        );
+
+        let overlay = extraction("[patch.crates-io]\nzamm_yin = false\n", &[]);
+        let changes = apply_override(&mut base, &overlay, "zamm_override.md").unwrap();
+        assert_eq!(
+            changes,
+            vec![OverrideChange::TomlRemoved {
+                key: "patch.crates-io.zamm_yin".to_owned(),
+                from: "{ path = \"../yin\" }".to_owned(),
+            }]
+        );
+        let merged: Value = base.toml.parse().unwrap();
+        assert_eq!(merged["zamm_yang"]["path"].as_str(), Some("../yang"));
+        assert_eq!(merged["patch"]["crates-io"].as_table().unwrap().len(), 0);
+        assert!(merged.get("crates-io").is_none());
+    }
+
+    #[test]
+    fn test_invalid_toml() {
+        let mut base = extraction("zamm_yang = \"0.1.5\"", &[]);
+        let overlay = extraction("zamm_yang = ", &[]);
+        assert!(apply_override(&mut base, &overlay, "zamm_override.md").is_err());
+    }
+
+    #[test]
+    fn test_imports_replaced_by_name() {
+        let mut base = extraction(
+            "",
+            &[
+                "https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md",
+                "https://api.zamm.dev/v1/books/zamm/yang/0.1.5/yang.md",
+                "helpers.md",
+            ],
+        );
+        let overlay = extraction(
+            "",
+            &[
+                "../yin/yin.md#name=zamm/yin",
+                "!helpers.md",
+                "https://example.com/extra.md",
+            ],
+        );
+        let changes = apply_override(&mut base, &overlay, "zamm_override.md").unwrap();
+        assert_eq!(
+            base.imports,
+            vec![
+                "../yin/yin.md#name=zamm/yin",
+                "https://api.zamm.dev/v1/books/zamm/yang/0.1.5/yang.md",
+                "https://example.com/extra.md",
+            ]
+        );
+        assert_eq!(
+            changes,
+            vec![
+                OverrideChange::ImportReplaced {
+                    from: "https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md".to_owned(),
+                    to: "../yin/yin.md#name=zamm/yin".to_owned(),
+                },
+                OverrideChange::ImportRemoved("helpers.md".to_owned()),
+                OverrideChange::ImportAdded("https://example.com/extra.md".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unnamed_local_import_replaces_network_import() {
+        // overrides written before imports could be named
+        let mut base = extraction("", &["https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md"]);
+        let changes = apply_override(
+            &mut base,
+            &extraction("", &["../yin/yin.md"]),
+            "zamm_override.md",
+        )
+        .unwrap();
+        assert_eq!(base.imports, vec!["../yin/yin.md"]);
+        assert_eq!(
+            changes,
+            vec![OverrideChange::ImportReplaced {
+                from: "https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md".to_owned(),
+                to: "../yin/yin.md".to_owned(),
+            }]
+        );
+
+        // there's no telling which book a local checkout is for when there are several
+        let mut base = extraction(
+            "",
+            &[
+                "https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md",
+                "https://api.zamm.dev/v1/books/zamm/yang/0.1.5/yang.md",
+            ],
+        );
+        let overlay = extraction("", &["../yin/yin.md"]);
+        let error = apply_override(&mut base, &overlay, "zamm_override.md").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error
+            .to_string()
+            .contains("../yin/yin.md#name=<org>/<book>"));
+
+        // unless the others are overridden by name
+        let overlay = extraction("", &["../yin/yin.md", "!zamm/yang"]);
+        apply_override(&mut base, &overlay, "zamm_override.md").unwrap();
+        assert_eq!(base.imports, vec!["../yin/yin.md"]);
+    }
+
+    #[test]
+    fn test_import_removed_by_name() {
+        let mut base = extraction("", &["https://api.zamm.dev/v1/books/zamm/yin/0.1.0/yin.md"]);
+        apply_override(
+            &mut base,
+            &extraction("", &["!zamm/yin"]),
+            "zamm_override.md",
+        )
+        .unwrap();
+        assert!(base.imports.is_empty());
+    }
+
+    #[test]
+    fn test_rust_appended() {
+        let mut base = CodeExtraction {
+            rust: "let x = 1;".to_owned(),
+            ..CodeExtraction::default()
+        };
+        let overlay = CodeExtraction {
+            rust: "let y = 2;\nlet z = 3;\n".to_owned(),
+            ..CodeExtraction::default()
+        };
+        let changes = apply_override(&mut base, &overlay, "zamm_override.md").unwrap();
+        assert_eq!(base.rust, "let x = 1;\nlet y = 2;\nlet z = 3;\n");
+        assert_eq!(changes, vec![OverrideChange::RustAppended { lines: 2 }]);
+    }
+}
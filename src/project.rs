@@ -0,0 +1,83 @@
+use crate::config::ZammConfig;
+use crate::parse::ParseConfig;
+use std::env;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// Directory inside the project root to put intermediate build files in.
+pub const ZAMM_INTERMEDIATE_DIR: &str = ".zamm";
+
+/// The project that ZAMM runs on. Every project file is found relative to the project root, rather
+/// than relative to the working directory of the process, so that several projects can be built at
+/// once from the same process.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Project {
+    /// Absolute path to the project's root directory.
+    pub root: PathBuf,
+}
+
+impl Project {
+    /// The project rooted at the given directory. Relative directories are taken to be relative to
+    /// the current working directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref();
+        Ok(Self {
+            root: if root.is_absolute() {
+                root.to_path_buf()
+            } else {
+                env::current_dir()?.join(root)
+            },
+        })
+    }
+
+    /// The project rooted at the current working directory.
+    pub fn current() -> Result<Self> {
+        Ok(Self {
+            root: env::current_dir()?,
+        })
+    }
+
+    /// Resolve a path relative to the project root. Absolute paths are left as they are.
+    pub fn path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Directory where intermediate build files go.
+    pub fn build_dir(&self) -> PathBuf {
+        self.path(ZAMM_INTERMEDIATE_DIR)
+    }
+
+    /// Load the project configuration.
+    pub fn config(&self) -> Result<ZammConfig> {
+        ZammConfig::load_from(&self.root)
+    }
+
+    /// Parse settings from the environment and the project configuration.
+    pub fn parse_config(&self, zamm_cfg: &ZammConfig) -> Result<ParseConfig> {
+        let mut parse_cfg = ParseConfig::from_env()?;
+        parse_cfg.project_root = self.root.clone();
+        zamm_cfg.apply_to(&mut parse_cfg);
+        Ok(parse_cfg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        let root = env::temp_dir().join("zamm-project");
+        let project = Project::new(&root).unwrap();
+        assert_eq!(project.build_dir(), root.join(".zamm"));
+        assert_eq!(project.path("yin.md"), root.join("yin.md"));
+        assert_eq!(project.path(&root), root);
+    }
+
+    #[test]
+    fn test_relative_root() {
+        let project = Project::new("book").unwrap();
+        assert!(project.root.is_absolute());
+        assert!(project.root.ends_with("book"));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::project::Project;
+use serde::{Serialize, Serializer};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io::Result;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// File in the build directory that timings get written to as JSON.
+pub const TIMINGS_FILE: &str = "timings.json";
+
+/// A part of the code generation pipeline.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// Reading the input file and extracting code from it.
+    Parse,
+    /// Retrieving imports, whether downloaded, cached, or local.
+    Imports,
+    /// Merging override files into the input file.
+    Overrides,
+    /// Generating the codegen binary's sources and writing them to the build directory.
+    BuildDir,
+    /// Building the codegen binary with Cargo.
+    CargoBuild,
+    /// Running the codegen binary.
+    Codegen,
+    /// Recording what the codegen binary generated.
+    PostProcess,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse => write!(f, "parse"),
+            Self::Imports => write!(f, "imports"),
+            Self::Overrides => write!(f, "overrides"),
+            Self::BuildDir => write!(f, "build-dir"),
+            Self::CargoBuild => write!(f, "cargo-build"),
+            Self::Codegen => write!(f, "codegen"),
+            Self::PostProcess => write!(f, "post-process"),
+        }
+    }
+}
+
+/// How long a phase took, summed over every time it ran.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct PhaseTiming {
+    /// The phase that was timed.
+    pub phase: Phase,
+    /// Total time spent in the phase.
+    #[serde(rename = "seconds", serialize_with = "as_seconds")]
+    pub duration: Duration,
+    /// Number of times the phase ran.
+    pub count: u32,
+}
+
+/// How long each phase of a build took. Phases that never ran, such as the Cargo build when a
+/// cached codegen binary gets reused, are left out.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+pub struct Timings {
+    /// Timings for each phase, in the order that they first ran.
+    pub phases: Vec<PhaseTiming>,
+    /// Total time taken, including time spent outside of any phase.
+    #[serde(rename = "total_seconds", serialize_with = "as_seconds")]
+    pub total: Duration,
+}
+
+/// Serialize durations as fractional seconds, which are easier for other tools to work with.
+fn as_seconds<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+thread_local! {
+    /// Timings being recorded on the current thread, if any.
+    static CURRENT: RefCell<Option<Timings>> = const { RefCell::new(None) };
+}
+
+impl Timings {
+    /// Add time spent in a phase.
+    pub fn add(&mut self, phase: Phase, duration: Duration) {
+        match self.phases.iter_mut().find(|t| t.phase == phase) {
+            Some(timing) => {
+                timing.duration += duration;
+                timing.count += 1;
+            }
+            None => self.phases.push(PhaseTiming {
+                phase,
+                duration,
+                count: 1,
+            }),
+        }
+    }
+
+    /// Time spent in the given phase.
+    pub fn get(&self, phase: Phase) -> Option<&PhaseTiming> {
+        self.phases.iter().find(|t| t.phase == phase)
+    }
+
+    /// Where timings for the given project get written to.
+    pub fn path(project: &Project) -> PathBuf {
+        project.build_dir().join(TIMINGS_FILE)
+    }
+
+    /// Write these timings to the project's build directory as JSON.
+    pub fn save(&self, project: &Project) -> Result<()> {
+        fs::create_dir_all(project.build_dir())?;
+        fs::write(Self::path(project), serde_json::to_string_pretty(self)?)
+    }
+
+    /// Table of how long each phase took, and what share of the total that was.
+    pub fn summary(&self) -> String {
+        let total = self.total.as_secs_f64();
+        let mut summary = format!("{:<14}{:>10}{:>8}", "Phase", "Time", "Share");
+        for timing in &self.phases {
+            let seconds = timing.duration.as_secs_f64();
+            let share = if total > 0.0 {
+                100.0 * seconds / total
+            } else {
+                0.0
+            };
+            summary += &format!(
+                "\n{:<14}{:>9.2}s{:>7.1}%",
+                timing.phase.to_string(),
+                seconds,
+                share
+            );
+        }
+        summary += &format!("\n{:<14}{:>9.2}s", "total", total);
+        summary
+    }
+}
+
+/// Run the given part of the pipeline, and count the time it takes towards the given phase if
+/// timings are being recorded on this thread.
+pub fn time<T, F: FnOnce() -> T>(phase: Phase, f: F) -> T {
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    CURRENT.with(|current| {
+        if let Some(timings) = current.borrow_mut().as_mut() {
+            timings.add(phase, elapsed);
+        }
+    });
+    result
+}
+
+/// Record how long each phase takes while running the given function, such as a call to
+/// `generate_code`. Only phases that run on the current thread are recorded, so that builds on
+/// other threads don't get mixed in.
+pub fn record<T, F: FnOnce() -> T>(f: F) -> (T, Timings) {
+    let outer = CURRENT.with(|current| current.replace(Some(Timings::default())));
+    let start = Instant::now();
+    let result = f();
+    let total = start.elapsed();
+    let mut timings = CURRENT
+        .with(|current| current.replace(outer))
+        .unwrap_or_default();
+    timings.total = total;
+    (result, timings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_phases() {
+        let (result, timings) = record(|| {
+            time(Phase::Parse, || ());
+            time(Phase::Imports, || ());
+            time(Phase::Parse, || 3)
+        });
+        assert_eq!(result, 3);
+        let phases: Vec<Phase> = timings.phases.iter().map(|t| t.phase).collect();
+        assert_eq!(phases, vec![Phase::Parse, Phase::Imports]);
+        assert_eq!(timings.get(Phase::Parse).unwrap().count, 2);
+        assert!(timings.get(Phase::CargoBuild).is_none());
+    }
+
+    #[test]
+    fn test_nothing_recorded_outside_recording() {
+        time(Phase::Codegen, || ());
+        let (_, timings) = record(|| ());
+        assert!(timings.phases.is_empty());
+    }
+
+    #[test]
+    fn test_json_in_seconds() {
+        let mut timings = Timings {
+            total: Duration::from_millis(2500),
+            ..Timings::default()
+        };
+        timings.add(Phase::PostProcess, Duration::from_millis(500));
+        assert_eq!(
+            serde_json::to_value(&timings).unwrap(),
+            serde_json::json!({
+                "phases": [{"phase": "post-process", "seconds": 0.5, "count": 1}],
+                "total_seconds": 2.5,
+            })
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut timings = Timings {
+            total: Duration::from_secs(4),
+            ..Timings::default()
+        };
+        timings.add(Phase::CargoBuild, Duration::from_secs(3));
+        assert_eq!(
+            timings.summary(),
+            "Phase               Time   Share\n\
+            cargo-build        3.00s   75.0%\n\
+            total              4.00s"
+        );
+    }
+}
@@ -0,0 +1,468 @@
+use crate::config::ZammConfig;
+use crate::parse::locate_file;
+use crate::project::Project;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use toml::Value;
+
+/// Filename for Cargo manifests.
+const CARGO_FILE: &str = "Cargo.toml";
+
+/// Sections of a Cargo manifest that can contain path dependencies on other workspace members.
+const DEPENDENCY_SECTIONS: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// A crate in a Cargo workspace.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Member {
+    /// Name of the crate's package.
+    pub name: String,
+    /// The crate as a ZAMM project.
+    pub project: Project,
+    /// Names of the other workspace members that this crate depends on by path.
+    pub dependencies: BTreeSet<String>,
+    /// Whether or not the crate has an input file for ZAMM to build.
+    pub literate: bool,
+}
+
+/// A Cargo workspace with one or more literate crates.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Workspace {
+    /// Absolute path to the directory containing the workspace `Cargo.toml`.
+    pub root: PathBuf,
+    /// Every member of the workspace, sorted by name.
+    pub members: Vec<Member>,
+}
+
+/// What happened to a workspace member during a workspace-wide command.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The command succeeded for this member after the given amount of time.
+    Succeeded(Duration),
+    /// The command failed for this member.
+    Failed(Error),
+    /// The command never ran because the named dependency failed.
+    Skipped(String),
+}
+
+/// What happened to each literate member of a workspace, in the order they were run.
+#[derive(Debug, Default)]
+pub struct WorkspaceReport {
+    /// Name of each member, along with its outcome.
+    pub outcomes: Vec<(String, Outcome)>,
+}
+
+impl WorkspaceReport {
+    /// Number of members that the command didn't succeed for.
+    pub fn failures(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|(_, o)| !matches!(o, Outcome::Succeeded(_)))
+            .count()
+    }
+
+    /// Human-readable summary of what happened to each member.
+    pub fn summary(&self) -> String {
+        let mut summary = "Workspace summary:".to_owned();
+        for (name, outcome) in &self.outcomes {
+            summary += &match outcome {
+                Outcome::Succeeded(time) => {
+                    format!("\n  ok      {} ({:.1}s)", name, time.as_secs_f64())
+                }
+                Outcome::Failed(e) => format!("\n  failed  {}: {}", name, e),
+                Outcome::Skipped(dependency) => {
+                    format!("\n  skipped {} because {} failed", name, dependency)
+                }
+            };
+        }
+        summary
+    }
+}
+
+impl fmt::Display for WorkspaceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary())
+    }
+}
+
+/// Read the Cargo manifest in the given directory.
+fn read_manifest(dir: &Path) -> Result<Value> {
+    let path = dir.join(CARGO_FILE);
+    fs::read_to_string(&path)?.parse::<Value>().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid Cargo manifest at {}: {}", path.display(), e),
+        )
+    })
+}
+
+/// Whether or not a single path component matches a pattern with at most one `*` in it.
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            name.len() >= prefix.len() + suffix.len()
+                && name.starts_with(prefix)
+                && name.ends_with(suffix)
+        }
+        None => name == pattern,
+    }
+}
+
+/// Every directory under the root that matches a workspace member pattern such as `crates/*`.
+fn expand_member_pattern(root: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![root.to_path_buf()];
+    for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        let mut matches = vec![];
+        for dir in dirs {
+            if !component.contains('*') {
+                matches.push(dir.join(component));
+                continue;
+            }
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                if entry.file_type()?.is_dir()
+                    && matches_pattern(&name.to_string_lossy(), component)
+                {
+                    matches.push(entry.path());
+                }
+            }
+        }
+        dirs = matches;
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Strings in the given array of the workspace table.
+fn workspace_strings<'a>(workspace: &'a Value, key: &str) -> Vec<&'a str> {
+    workspace
+        .get(key)
+        .and_then(Value::as_array)
+        .map(|a| a.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// Directories of every path dependency declared in the given crate manifest.
+fn path_dependencies(dir: &Path, manifest: &Value) -> Vec<PathBuf> {
+    DEPENDENCY_SECTIONS
+        .iter()
+        .filter_map(|section| manifest.get(section).and_then(Value::as_table))
+        .flat_map(|deps| deps.values())
+        .filter_map(|dep| dep.get("path").and_then(Value::as_str))
+        .map(|path| dir.join(path))
+        .collect()
+}
+
+impl Workspace {
+    /// Load the workspace whose `Cargo.toml` is in the given directory, along with every member
+    /// that it lists. Members count as literate if they have an input file, whether it's the one
+    /// set in their own ZAMM configuration or the default one.
+    pub fn load(root: &Path) -> Result<Self> {
+        let root = Project::new(root)?.root;
+        let manifest = read_manifest(&root)?;
+        let workspace = manifest.get("workspace").ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "No [workspace] section in {}",
+                    root.join(CARGO_FILE).display()
+                ),
+            )
+        })?;
+
+        let excluded: Vec<PathBuf> = workspace_strings(workspace, "exclude")
+            .iter()
+            .map(|e| root.join(e))
+            .collect();
+        let mut dirs = BTreeSet::new();
+        if manifest.get("package").is_some() {
+            dirs.insert(root.clone());
+        }
+        for pattern in workspace_strings(workspace, "members") {
+            for dir in expand_member_pattern(&root, pattern)? {
+                if !excluded.contains(&dir) && dir.join(CARGO_FILE).exists() {
+                    dirs.insert(dir);
+                }
+            }
+        }
+
+        // match up path dependencies with members by their canonical paths
+        let mut names = BTreeMap::new();
+        let mut manifests = vec![];
+        for dir in dirs {
+            let member_manifest = read_manifest(&dir)?;
+            let name = member_manifest
+                .get("package")
+                .and_then(|p| p.get("name"))
+                .and_then(Value::as_str)
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("No package name in {}", dir.join(CARGO_FILE).display()),
+                    )
+                })?
+                .to_owned();
+            names.insert(dir.canonicalize()?, name.clone());
+            manifests.push((dir, name, member_manifest));
+        }
+
+        let mut members = vec![];
+        for (dir, name, member_manifest) in manifests {
+            let dependencies = path_dependencies(&dir, &member_manifest)
+                .iter()
+                .filter_map(|d| d.canonicalize().ok())
+                .filter_map(|d| names.get(&d).cloned())
+                .filter(|d| d != &name)
+                .collect();
+            let project = Project::new(&dir)?;
+            let zamm_cfg = ZammConfig::load_from(&project.root)?;
+            let literate = locate_file(&project.root, zamm_cfg.input.as_deref()).is_ok();
+            members.push(Member {
+                name,
+                project,
+                dependencies,
+                literate,
+            });
+        }
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self { root, members })
+    }
+
+    /// Every literate member of the workspace.
+    pub fn literate_members(&self) -> impl Iterator<Item = &Member> {
+        self.members.iter().filter(|m| m.literate)
+    }
+
+    /// Every member of the workspace, grouped so that each member comes after all of its
+    /// dependencies. Members in the same group don't depend on each other.
+    pub fn build_order(&self) -> Result<Vec<Vec<&Member>>> {
+        let mut remaining: Vec<&Member> = self.members.iter().collect();
+        let mut done = BTreeSet::new();
+        let mut levels = vec![];
+        while !remaining.is_empty() {
+            let (ready, blocked): (Vec<&Member>, Vec<&Member>) = remaining
+                .into_iter()
+                .partition(|m| m.dependencies.iter().all(|d| done.contains(d)));
+            if ready.is_empty() {
+                let cycle: Vec<&str> = blocked.iter().map(|m| m.name.as_str()).collect();
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Workspace members depend on each other in a cycle: {}",
+                        cycle.join(", ")
+                    ),
+                ));
+            }
+            done.extend(ready.iter().map(|m| m.name.clone()));
+            levels.push(ready);
+            remaining = blocked;
+        }
+        Ok(levels)
+    }
+
+    /// Run a task on every literate member of the workspace, in dependency order. Members that
+    /// don't depend on each other are run in parallel. If the task fails for a member, it is
+    /// skipped for every member that depends on it, directly or through crates without input
+    /// files.
+    pub fn run<F>(&self, task: F) -> Result<WorkspaceReport>
+    where
+        F: Fn(&Member) -> Result<()> + Sync,
+    {
+        let mut report = WorkspaceReport::default();
+        // the literate member responsible for each failed member, literate or not
+        let mut failed: BTreeMap<String, String> = BTreeMap::new();
+        for level in self.build_order()? {
+            let mut runnable = vec![];
+            for member in level {
+                match member.dependencies.iter().find_map(|d| failed.get(d)) {
+                    Some(cause) => {
+                        let cause = cause.clone();
+                        if member.literate {
+                            report
+                                .outcomes
+                                .push((member.name.clone(), Outcome::Skipped(cause.clone())));
+                        }
+                        failed.insert(member.name.clone(), cause);
+                    }
+                    None if member.literate => runnable.push(member),
+                    None => (),
+                }
+            }
+
+            let outcomes: Vec<Outcome> = thread::scope(|scope| {
+                let handles: Vec<_> = runnable
+                    .iter()
+                    .map(|member| {
+                        let task = &task;
+                        scope.spawn(move || {
+                            let start = Instant::now();
+                            match task(member) {
+                                Ok(()) => Outcome::Succeeded(start.elapsed()),
+                                Err(e) => Outcome::Failed(e),
+                            }
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| {
+                        h.join().unwrap_or_else(|_| {
+                            Outcome::Failed(Error::new(ErrorKind::Other, "Task panicked"))
+                        })
+                    })
+                    .collect()
+            });
+            for (member, outcome) in runnable.into_iter().zip(outcomes) {
+                if let Outcome::Failed(_) = outcome {
+                    failed.insert(member.name.clone(), member.name.clone());
+                }
+                report.outcomes.push((member.name.clone(), outcome));
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{find_file, parse_input};
+    use tempfile::TempDir;
+
+    fn write_crate(root: &Path, dir: &str, name: &str, deps: &[&str], literate: bool) {
+        let dir = root.join(dir);
+        fs::create_dir_all(&dir).unwrap();
+        let deps: Vec<String> = deps
+            .iter()
+            .map(|d| format!("{} = {{ path = \"../{}\" }}", d, d))
+            .collect();
+        fs::write(
+            dir.join(CARGO_FILE),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\n{}\n",
+                name,
+                deps.join("\n")
+            ),
+        )
+        .unwrap();
+        if literate {
+            fs::write(dir.join("yin.md"), "# Book").unwrap();
+        }
+    }
+
+    fn temp_workspace() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(
+            root.join(CARGO_FILE),
+            "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/old\"]\n",
+        )
+        .unwrap();
+        // a depends on nothing, c is not literate and depends on a, and b depends on c
+        write_crate(root, "crates/a", "a", &[], true);
+        write_crate(root, "crates/b", "b", &["c"], true);
+        write_crate(root, "crates/c", "c", &["a"], false);
+        write_crate(root, "crates/d", "d", &[], true);
+        write_crate(root, "crates/old", "old", &[], true);
+        dir
+    }
+
+    fn names(members: &[&Member]) -> Vec<String> {
+        members.iter().map(|m| m.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("zamm_yin", "zamm_*"));
+        assert!(matches_pattern("anything", "*"));
+        assert!(!matches_pattern("yang", "zamm_*"));
+        assert!(!matches_pattern("ab", "ab*b"));
+    }
+
+    #[test]
+    fn test_load_workspace() {
+        let dir = temp_workspace();
+        let root = dir.path();
+        let workspace = Workspace::load(root).unwrap();
+        let all: Vec<&Member> = workspace.members.iter().collect();
+        assert_eq!(names(&all), vec!["a", "b", "c", "d"]);
+        let literate: Vec<&Member> = workspace.literate_members().collect();
+        assert_eq!(names(&literate), vec!["a", "b", "d"]);
+        assert_eq!(
+            workspace.members[1].dependencies,
+            vec!["c".to_owned()].into_iter().collect()
+        );
+
+        let levels: Vec<Vec<String>> = workspace
+            .build_order()
+            .unwrap()
+            .iter()
+            .map(|l| names(l))
+            .collect();
+        assert_eq!(levels, vec![vec!["a", "d"], vec!["c"], vec!["b"]]);
+    }
+
+    #[test]
+    fn test_dependency_cycle() {
+        let dir = temp_workspace();
+        let root = dir.path();
+        write_crate(root, "crates/a", "a", &["b"], true);
+        let workspace = Workspace::load(root).unwrap();
+        let error = workspace.build_order().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("a, b, c"));
+    }
+
+    #[test]
+    fn test_failures_skip_dependents() {
+        let dir = temp_workspace();
+        let root = dir.path();
+        let workspace = Workspace::load(root).unwrap();
+        let report = workspace
+            .run(|member| match member.name.as_str() {
+                "a" => Err(Error::new(ErrorKind::InvalidData, "broken book")),
+                _ => Ok(()),
+            })
+            .unwrap();
+        let outcomes: Vec<(&str, &Outcome)> = report
+            .outcomes
+            .iter()
+            .map(|(n, o)| (n.as_str(), o))
+            .collect();
+        assert!(matches!(outcomes[0], ("a", Outcome::Failed(_))));
+        assert!(matches!(outcomes[1], ("d", Outcome::Succeeded(_))));
+        assert!(matches!(outcomes[2], ("b", Outcome::Skipped(cause)) if cause == "a"));
+        assert_eq!(report.failures(), 2);
+        assert!(report.summary().contains("failed  a: broken book"));
+        assert!(report.summary().contains("skipped b because a failed"));
+    }
+
+    #[test]
+    fn test_import_from_other_member() {
+        let dir = temp_workspace();
+        let root = dir.path();
+        fs::write(root.join("crates/a/yin.md"), "```rust\nlet a = 1;\n```\n").unwrap();
+        fs::write(
+            root.join("crates/b/yin.md"),
+            "```zamm\n../a/yin.md\n```\n\n```rust\nlet b = a;\n```\n",
+        )
+        .unwrap();
+        let workspace = Workspace::load(root).unwrap();
+        let report = workspace
+            .run(|member| {
+                let parse_cfg = member.project.parse_config(&member.project.config()?)?;
+                let input = parse_input(find_file(&member.project.root, None)?, &parse_cfg)?;
+                if member.name == "b" {
+                    assert_eq!(input.extractions.imported_rust, "let a = 1;\n");
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(report.failures(), 0, "{}", report);
+    }
+}